@@ -5,17 +5,26 @@ use sdl2::audio::AudioDevice;
 use sdl2::audio::AudioSpecDesired;
 use sdl2::audio::AudioStatus;
 
+pub(crate) const SAMPLE_RATE: i32 = 44_100;
+
 #[must_use]
-struct SquareWave {
+pub(crate) struct SquareWave {
     phase_inc: f64,
     phase: f64,
     volume: f32,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+impl SquareWave {
+    pub(crate) fn new(freq: i32) -> Self {
+        Self {
+            phase_inc: 440.0 / f64::from(freq),
+            phase: 0.0,
+            volume: 0.25,
+        }
+    }
 
-    fn callback(&mut self, out: &mut [f32]) {
+    /// Generates the next `out.len()` samples of the buzzer.
+    pub(crate) fn fill(&mut self, out: &mut [f32]) {
         // Generate a square wave
         for x in out {
             *x = if self.phase <= 0.5 {
@@ -28,6 +37,14 @@ impl AudioCallback for SquareWave {
     }
 }
 
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.fill(out);
+    }
+}
+
 #[must_use]
 pub(super) struct Audio {
     device: AudioDevice<SquareWave>,
@@ -36,7 +53,7 @@ pub(super) struct Audio {
 impl Audio {
     pub(super) fn new(context: &sdl2::Sdl) -> Result<Self> {
         let desired_spec = AudioSpecDesired {
-            freq: Some(44_100),
+            freq: Some(SAMPLE_RATE),
             channels: Some(1), // mono
             samples: None,     // default sample size
         };
@@ -47,11 +64,7 @@ impl Audio {
                 .map_err(Error::msg)?
                 .open_playback(None, &desired_spec, |spec| -> SquareWave {
                     // initialize the audio callback
-                    SquareWave {
-                        phase_inc: 440.0 / f64::from(spec.freq),
-                        phase: 0.0,
-                        volume: 0.25,
-                    }
+                    SquareWave::new(spec.freq)
                 })
                 .map_err(Error::msg)?,
         })
@@ -0,0 +1,357 @@
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::chip8;
+use crate::headless;
+
+// the most search results listed at once
+const LIST_LIMIT: usize = 32;
+
+const HELP: &str = "\
+new                  start a search over all of memory
+equal VALUE          keep addresses holding VALUE (hex)
+changed | unchanged  keep addresses that changed, or not, since the last search
+increased | decreased
+list                 show the addresses still in the search
+freeze WHERE VALUE   hold an address or register (V0-VF) at VALUE every frame
+unfreeze WHERE       stop holding it
+freezes              show what is frozen
+save                 write the freezes to the cheat file";
+
+/// Somewhere a cheat can hold a value.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Location {
+    Memory(u16),
+    Register(u8),
+}
+
+impl Location {
+    fn parse(value: &str) -> Result<Self> {
+        let upper = value.to_ascii_uppercase();
+        if let Some(x) = upper.strip_prefix('V') {
+            return u8::from_str_radix(x, 16)
+                .ok()
+                .filter(|x| *x <= 0xF)
+                .map(Self::Register)
+                .ok_or_else(|| anyhow!("invalid register '{value}'"));
+        }
+
+        Ok(Self::Memory(headless::parse_address(value)?))
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Memory(address) => write!(f, "{address:04X}"),
+            Self::Register(x) => write!(f, "V{x:X}"),
+        }
+    }
+}
+
+/// A location held at a fixed value.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Freeze {
+    location: Location,
+    value: u8,
+}
+
+fn parse_value(value: &str) -> Result<u8> {
+    let value = value.trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(value, 16).with_context(|| format!("invalid value '{value}'"))
+}
+
+/// Parses a cheat file: one `WHERE=VALUE` per line, where `WHERE` is a hex
+/// address or a register `V0`-`VF` and `VALUE` a hex byte. Anything after a
+/// `#` is a comment.
+fn parse(contents: &str) -> Result<Vec<Freeze>> {
+    let mut freezes = Vec::new();
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (location, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected WHERE=VALUE", num + 1))?;
+
+        freezes.push(Freeze {
+            location: Location::parse(location.trim())
+                .with_context(|| format!("line {}", num + 1))?,
+            value: parse_value(value.trim()).with_context(|| format!("line {}", num + 1))?,
+        });
+    }
+
+    Ok(freezes)
+}
+
+/// The addresses that could still hold the value being looked for, and the
+/// memory they were last compared against.
+#[derive(Debug)]
+struct Search {
+    candidates: Vec<u16>,
+    snapshot: Vec<u8>,
+}
+
+impl Search {
+    fn new(memory: &[u8]) -> Self {
+        Self {
+            candidates: (0..=u16::MAX).take(memory.len()).collect(),
+            snapshot: memory.to_vec(),
+        }
+    }
+
+    /// Keeps the candidates for which `keep(old, new)` is true, then takes a
+    /// new snapshot.
+    fn narrow(&mut self, memory: &[u8], keep: impl Fn(u8, u8) -> bool) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|a| {
+            let a = usize::from(*a);
+            keep(snapshot[a], memory[a])
+        });
+        self.snapshot = memory.to_vec();
+    }
+}
+
+/// Values held every frame, read from a per-ROM `<rom>.cheats` file, and the
+/// state of a memory search run from commands typed on stdin.
+#[derive(Debug, Default)]
+#[must_use]
+pub(super) struct Cheats {
+    path: PathBuf,
+    freezes: Vec<Freeze>,
+    search: Option<Search>,
+    console: Option<Receiver<String>>,
+}
+
+impl Cheats {
+    /// Loads the freezes from `<rom>.cheats` next to the ROM, if there is one.
+    pub(super) fn load(rom_filename: &str) -> Result<Self> {
+        let path = Path::new(rom_filename).with_extension("cheats");
+
+        let freezes = if path.is_file() {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read cheats {}", path.display()))?;
+            parse(&contents).with_context(|| format!("Invalid cheats {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            freezes,
+            ..Self::default()
+        })
+    }
+
+    /// Reads commands from stdin while the ROM runs.
+    pub(super) fn start_console(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("cheat console ready, type 'help' for commands");
+        self.console = Some(receiver);
+    }
+
+    /// Runs any commands typed since the last frame, then writes the frozen
+    /// values.
+    pub(super) fn apply(&mut self, chip8: &mut chip8::Chip8) {
+        let commands: Vec<String> = self
+            .console
+            .as_ref()
+            .map(|c| c.try_iter().collect())
+            .unwrap_or_default();
+
+        for command in commands {
+            match self.command(&command, chip8.memory()) {
+                Ok(reply) if reply.is_empty() => {}
+                Ok(reply) => println!("{reply}"),
+                Err(e) => println!("{e:#}"),
+            }
+        }
+
+        for freeze in &self.freezes {
+            match freeze.location {
+                Location::Memory(address) => chip8.poke(address, freeze.value),
+                Location::Register(x) => chip8.set_register(x, freeze.value),
+            }
+        }
+    }
+
+    /// Runs a console command, returning what to print.
+    fn command(&mut self, line: &str, memory: &[u8]) -> Result<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let keep: fn(u8, u8) -> bool = match words[..] {
+            [] => return Ok(String::new()),
+            ["help"] => return Ok(HELP.into()),
+            ["new"] => {
+                self.search = Some(Search::new(memory));
+                return Ok(format!("searching {} addresses", memory.len()));
+            }
+            ["equal", value] => {
+                let value = parse_value(value)?;
+                let search = self.search.get_or_insert_with(|| Search::new(memory));
+                search.narrow(memory, |_, new| new == value);
+                return Ok(format!("{} addresses left", search.candidates.len()));
+            }
+            ["changed"] => |old, new| old != new,
+            ["unchanged"] => |old, new| old == new,
+            ["increased"] => |old, new| new > old,
+            ["decreased"] => |old, new| new < old,
+            ["list"] => return Ok(self.list(memory)),
+            ["freeze", location, value] => {
+                let freeze = Freeze {
+                    location: Location::parse(location)?,
+                    value: parse_value(value)?,
+                };
+                self.freezes.retain(|f| f.location != freeze.location);
+                self.freezes.push(freeze);
+                return Ok(format!(
+                    "{} frozen at {:02X}",
+                    freeze.location, freeze.value
+                ));
+            }
+            ["unfreeze", location] => {
+                let location = Location::parse(location)?;
+                self.freezes.retain(|f| f.location != location);
+                return Ok(format!("{location} unfrozen"));
+            }
+            ["freezes"] => return Ok(self.to_text()),
+            ["save"] => {
+                fs::write(&self.path, self.to_text())
+                    .with_context(|| format!("Unable to write {}", self.path.display()))?;
+                return Ok(format!("saved {}", self.path.display()));
+            }
+            _ => return Err(anyhow!("unknown command '{line}', try 'help'")),
+        };
+
+        let search = self
+            .search
+            .as_mut()
+            .ok_or_else(|| anyhow!("start a search with 'new' first"))?;
+        search.narrow(memory, keep);
+        Ok(format!("{} addresses left", search.candidates.len()))
+    }
+
+    fn list(&self, memory: &[u8]) -> String {
+        let Some(search) = &self.search else {
+            return "no search running".into();
+        };
+
+        let mut lines: Vec<String> = search
+            .candidates
+            .iter()
+            .take(LIST_LIMIT)
+            .map(|a| format!("{a:04X}={:02X}", memory[usize::from(*a)]))
+            .collect();
+        if search.candidates.len() > LIST_LIMIT {
+            lines.push(format!(
+                "... and {} more",
+                search.candidates.len() - LIST_LIMIT
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// The freezes in the cheat file format.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for freeze in &self.freezes {
+            let _ = writeln!(text, "{}={:02X}", freeze.location, freeze.value);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use anyhow::Error;
+
+    use super::parse;
+    use super::Cheats;
+    use super::Freeze;
+    use super::Location;
+    use crate::chip8::Chip8;
+    use crate::profile;
+    use crate::Target;
+
+    #[test]
+    fn test_parse() -> Result<(), Error> {
+        // when
+        let contents = "# lives\nV3=05\n\n3a0 = 0x09  # score\n";
+
+        // then
+        let freezes = parse(contents)?;
+
+        // verify
+        assert_eq!(
+            freezes,
+            vec![
+                Freeze {
+                    location: Location::Register(3),
+                    value: 5
+                },
+                Freeze {
+                    location: Location::Memory(0x3A0),
+                    value: 9
+                },
+            ]
+        );
+        assert!(parse("VG=01").is_err());
+        assert!(parse("300=100").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_and_freeze() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Chip8,
+            *profile::profiles()
+                .get(&Target::Chip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        let mut cheats = Cheats::default();
+        chip8.poke(0x300, 3);
+        chip8.poke(0x301, 3);
+
+        // then
+        cheats.command("equal 3", chip8.memory())?;
+        chip8.poke(0x300, 2);
+        let left = cheats.command("decreased", chip8.memory())?;
+        cheats.command("freeze 300 09", chip8.memory())?;
+        cheats.command("freeze VA 01", chip8.memory())?;
+        cheats.apply(&mut chip8);
+
+        // verify
+        assert_eq!(left, "1 addresses left");
+        assert_eq!(cheats.list(chip8.memory()), "0300=09");
+        assert_eq!(chip8.state().registers[0xA], 1);
+        assert_eq!(cheats.to_text(), "0300=09\nVA=01\n");
+        Ok(())
+    }
+}
@@ -1,16 +1,13 @@
-use std::collections::HashMap;
-use std::sync::OnceLock;
+use std::ops::RangeInclusive;
 
 use anyhow::anyhow;
-use anyhow::Context;
 use anyhow::Result;
 use bitvec::prelude::BitArray;
 use bitvec::prelude::BitVec;
 use bitvec::BitArr;
+use clap::ValueEnum;
 use rand::Rng;
 use rand::RngExt;
-use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
 
 use crate::profile;
 use crate::Action;
@@ -39,6 +36,50 @@ const NUMBER_OF_KEYS: usize = 16;
 const NUMBER_OF_REGISTERS: usize = 16;
 const STACK_SIZE: usize = 16;
 
+/// What FX0A makes of a key that's already down when it starts waiting.
+#[derive(ValueEnum, Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) enum HeldKeys {
+    /// Only take a key pressed after the wait starts
+    #[default]
+    Ignore,
+    /// Take a held key as though it had just been pressed, as the VIP did
+    Accept,
+}
+
+/// How far an FX0A instruction has got. As on the VIP, the key is only taken
+/// once it has been pressed and then released.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum KeyWait {
+    Idle,
+    Press,
+    Release(u8),
+    // released, to be stored when the instruction next runs
+    Done(u8),
+}
+
+/// A random number generator that can be copied along with the machine.
+pub(super) trait MachineRng: Rng {
+    fn boxed_clone(&self) -> Box<dyn MachineRng>;
+}
+
+impl<R: Rng + Clone + 'static> MachineRng for R {
+    fn boxed_clone(&self) -> Box<dyn MachineRng> {
+        Box::new(self.clone())
+    }
+}
+
+/// The registers visible to a program, as they stand between instructions.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) struct State {
+    pub(super) registers: [u8; NUMBER_OF_REGISTERS],
+    pub(super) i: u16,
+    pub(super) pc: u16,
+    pub(super) sp: u8,
+    pub(super) delay_timer: u8,
+    pub(super) sound_timer: u8,
+}
+
 #[must_use]
 pub(super) struct Chip8 {
     registers: [u8; NUMBER_OF_REGISTERS], // registers
@@ -50,7 +91,7 @@ pub(super) struct Chip8 {
 
     // 0x000-0x1FF - Chip 8 interpreter (contains font set in emu)
     // 0x050-0x0A0 - Used for the built in 4x5 pixel font set (0-F)
-    // 0x200-0xE8F - Program ROM and work RAM
+    // 0x200-0xE8F - Program ROM and work RAM (from 0x600 on the ETI-660)
     // 0xEA0-0xEFF - 'internal usage and variables'
     // 0xF00-0xFFF - 'display refresh'
     memory: Box<[u8]>,
@@ -71,15 +112,46 @@ pub(super) struct Chip8 {
     draw: bool,
     hires: bool,
 
+    // a draw has to wait for the next display interrupt before the program
+    // goes on
+    vblank_wait: bool,
+
     target: Target,
 
-    rng: Box<dyn Rng>,
+    rng: Box<dyn MachineRng>,
 
-    key_wait: (Option<bool>, Option<u8>),
+    key_wait: KeyWait,
+    held_keys: HeldKeys,
+}
+
+impl Clone for Chip8 {
+    fn clone(&self) -> Self {
+        Self {
+            registers: self.registers,
+            user_registers: self.user_registers.clone(),
+            i: self.i,
+            pc: self.pc,
+            memory: self.memory.clone(),
+            gfx: self.gfx.clone(),
+            profile: self.profile,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack,
+            sp: self.sp,
+            key: self.key,
+            draw: self.draw,
+            hires: self.hires,
+            vblank_wait: self.vblank_wait,
+            target: self.target,
+            rng: self.rng.boxed_clone(),
+            key_wait: self.key_wait,
+            held_keys: self.held_keys,
+        }
+    }
 }
 
 impl Chip8 {
-    pub(super) fn new(target: Target, profile: profile::Profile, rng: Box<dyn Rng>) -> Self {
+    pub(super) fn new(target: Target, profile: profile::Profile, rng: Box<dyn MachineRng>) -> Self {
         let user_registers = match profile.user_register_count() {
             ur if ur > 0 => Some(crate::util::boxed_array::<u8>(usize::from(ur))),
             _ => None,
@@ -105,7 +177,7 @@ impl Chip8 {
 
             i: 0,
 
-            pc: 0x200,
+            pc: profile.start_address(),
 
             delay_timer: 0,
             sound_timer: 0,
@@ -116,16 +188,36 @@ impl Chip8 {
 
             profile,
             key,
-            key_wait: (None, None),
+            key_wait: KeyWait::Idle,
+            held_keys: HeldKeys::default(),
 
             draw: true,
             hires: false,
+            vblank_wait: false,
         }
     }
 
-    // FIXME error if the rom_data is too large for the memory space ( 0x200-0xE8F )
-    pub(super) fn load_rom(&mut self, rom_data: &[u8]) {
-        self.memory[0x200..(0x200 + rom_data.len())].copy_from_slice(rom_data);
+    pub(super) fn set_held_keys(&mut self, held_keys: HeldKeys) {
+        self.held_keys = held_keys;
+    }
+
+    pub(super) fn set_rng(&mut self, rng: Box<dyn MachineRng>) {
+        self.rng = rng;
+    }
+
+    pub(super) fn load_rom(&mut self, rom_data: &[u8]) -> Result<()> {
+        let start = usize::from(self.profile.load_address());
+        let Some(memory) = self.memory.get_mut(start..start + rom_data.len()) else {
+            let target = self.target.to_possible_value();
+            return Err(anyhow!(
+                "ROM too large for {} ({} bytes, {} fit)",
+                target.as_ref().map_or("this target", |t| t.get_name()),
+                rom_data.len(),
+                self.memory.len().saturating_sub(start)
+            ));
+        };
+        memory.copy_from_slice(rom_data);
+        Ok(())
     }
 
     pub(super) fn graphics(&self) -> &BitVec {
@@ -140,12 +232,87 @@ impl Chip8 {
         self.draw = false;
     }
 
+    pub(super) fn program_counter(&self) -> u16 {
+        self.pc
+    }
+
+    /// The opcode of the next instruction to be executed
+    pub(super) fn opcode(&self) -> u16 {
+        (u16::from(self.memory[usize::from(self.pc)]) << 8)
+            | u16::from(self.memory[usize::from(self.pc + 1)])
+    }
+
+    pub(super) fn state(&self) -> State {
+        State {
+            registers: self.registers,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// The return addresses on the stack, oldest first.
+    pub(super) fn stack(&self) -> &[u16] {
+        &self.stack[..usize::from(self.sp)]
+    }
+
+    pub(super) fn key_pressed(&self, key_num: u8) -> bool {
+        self.key[usize::from(key_num)]
+    }
+
+    pub(super) fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Replaces the registers, e.g. from a debugger. SP is left alone if it
+    /// would be past the end of the stack.
+    pub(super) fn set_state(&mut self, state: State) {
+        self.registers = state.registers;
+        self.i = state.i;
+        self.pc = state.pc;
+        if usize::from(state.sp) <= STACK_SIZE {
+            self.sp = state.sp;
+        }
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+    }
+
+    /// The memory the next instruction will store to, if any.
+    pub(super) fn next_writes(&self) -> Option<RangeInclusive<u16>> {
+        let opcode = self.opcode();
+        let x = (opcode & 0x0F00) >> 8;
+
+        match opcode & 0xF0FF {
+            0xF033 => Some(self.i..=self.i.saturating_add(2)),
+            0xF055 => Some(self.i..=self.i.saturating_add(x)),
+            _ => None,
+        }
+    }
+
+    pub(super) fn set_register(&mut self, x: u8, value: u8) {
+        self.registers[usize::from(x & 0xF)] = value;
+    }
+
+    /// Writes a byte into memory, ignoring addresses past the end of it.
+    pub(super) fn poke(&mut self, address: u16, value: u8) {
+        if let Some(byte) = self.memory.get_mut(usize::from(address)) {
+            *byte = value;
+        }
+    }
+
     pub(super) fn hires_mode(&self) -> bool {
         self.hires
     }
 
     pub(super) fn resolution_scale(&self) -> u8 {
-        if self.hires || (self.target == Target::Chip8) {
+        if self.hires
+            || matches!(
+                self.target,
+                Target::Chip8 | Target::HiresChip8 | Target::Eti660 | Target::Chip48
+            )
+        {
             return 1;
         }
 
@@ -154,85 +321,51 @@ impl Chip8 {
 
     pub(super) fn audio_sound(&self) -> bool {
         self.sound_timer > 0
+            || (self.profile.key_wait_beep() && matches!(self.key_wait, KeyWait::Release(_)))
     }
 
-    pub(super) fn update_timers(&mut self) {
+    /// The 60 Hz display interrupt: counts the timers down and lets a program
+    /// waiting on a draw go on.
+    pub(super) fn vblank(&mut self) {
         self.delay_timer = self.delay_timer.saturating_sub(1);
         self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.vblank_wait = false;
     }
 
-    fn key_scan_mapping() -> &'static HashMap<Scancode, u8> {
-        static LOCK: OnceLock<HashMap<Scancode, u8>> = OnceLock::new();
-        LOCK.get_or_init(|| {
-            HashMap::from([
-                (Scancode::Num1, 0x1),
-                (Scancode::Num2, 0x2),
-                (Scancode::Num3, 0x3),
-                (Scancode::Num4, 0xc),
-                (Scancode::Q, 0x4),
-                (Scancode::W, 0x5),
-                (Scancode::E, 0x6),
-                (Scancode::R, 0xd),
-                (Scancode::A, 0x7),
-                (Scancode::S, 0x8),
-                (Scancode::D, 0x9),
-                (Scancode::F, 0xe),
-                (Scancode::Z, 0xa),
-                (Scancode::X, 0x0),
-                (Scancode::C, 0xb),
-                (Scancode::V, 0xf),
-            ])
-        })
-    }
-
-    fn set_key(&mut self, code: Scancode, pressed: bool) -> Result<()> {
-        let key_num = Self::key_scan_mapping()
-            .get(&code)
-            .context("invalid key code")?;
-
-        match self.key_wait {
-            (Some(false), Some(num)) if !pressed && num == *key_num => {
-                self.key_wait = (Some(true), Some(num));
-            }
-            (Some(false), None) if pressed => self.key_wait = (Some(false), Some(*key_num)),
-            _ => {}
-        }
-
-        if let Some(k) = self.key.get_mut(usize::from(*key_num)) {
-            k.commit(pressed);
-        }
+    /// True while an FX0A instruction is waiting for a key.
+    pub(super) fn waiting_for_key(&self) -> bool {
+        self.key_wait != KeyWait::Idle
+    }
 
-        Ok(())
+    /// True if the program can't go on until the next display interrupt.
+    pub(super) fn waiting_for_vblank(&self) -> bool {
+        self.vblank_wait
     }
 
-    pub(super) fn handle_key(&mut self, event: &Event) -> Result<Option<Action>> {
-        match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                scancode: Some(Scancode::Escape),
-                ..
-            } => Ok(Some(Action::Quit)),
-            Event::KeyDown {
-                repeat: false,
-                scancode: Some(code),
-                ..
+    /// Sets the state of a CHIP-8 key (0x0-0xF) directly
+    pub(super) fn press_key(&mut self, key_num: u8, pressed: bool) {
+        // only a change counts, so that a held key can be set down again
+        let was_pressed = self.key.get(usize::from(key_num)).is_some_and(|k| *k);
+        self.key_wait = match self.key_wait {
+            KeyWait::Press if pressed && !was_pressed => KeyWait::Release(key_num),
+            KeyWait::Release(num) if !pressed && was_pressed && num == key_num => {
+                KeyWait::Done(num)
             }
-            | Event::KeyUp {
-                repeat: false,
-                scancode: Some(code),
-                ..
-            } if Self::key_scan_mapping().contains_key(code) => self
-                .set_key(*code, matches!(event, Event::KeyDown { .. }))
-                .and(Ok(None)),
-            _ => Ok(None),
+            wait => wait,
+        };
+
+        if let Some(k) = self.key.get_mut(usize::from(key_num)) {
+            k.commit(pressed);
         }
     }
 
     pub(super) fn emulate_cycle(&mut self) -> Option<Action> {
-        let opcode = (u16::from(self.memory[usize::from(self.pc)]) << 8)
-            | u16::from(self.memory[usize::from(self.pc + 1)]);
+        let opcode = self.opcode();
 
-        let sc = matches!(self.target, Target::SuperChipLegacy | Target::SuperChip);
+        let sc = matches!(
+            self.target,
+            Target::SuperChip10 | Target::SuperChipLegacy | Target::SuperChip
+        );
         let xo = matches!(self.target, Target::XoChip);
 
         // these operations don't increment the program counter value
@@ -262,6 +395,8 @@ impl Chip8 {
 
         self.pc += match opcode {
             0x00E0 => self.c8_display_clear(),
+            // the hires interpreter's clear, as its own code is where 00E0's was
+            0x0230 if self.target == Target::HiresChip8 => self.c8_display_clear(),
 
             o if o & 0xFFF0 == 0x00C0 && (sc || xo) => self.sc_scroll_down(o),
             o if o & 0xFFF0 == 0x00D0 && xo => self.xo_scroll_up(o),
@@ -426,8 +561,12 @@ impl Chip8 {
 
         self.registers[reg_x] |= self.registers[reg_y];
         match self.target {
-            Target::Chip8 => self.registers[15] = 0,
-            Target::SuperChipLegacy | Target::SuperChip | Target::XoChip => {}
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 => self.registers[15] = 0,
+            Target::Chip48
+            | Target::SuperChip10
+            | Target::SuperChipLegacy
+            | Target::SuperChip
+            | Target::XoChip => {}
         }
 
         2
@@ -439,8 +578,12 @@ impl Chip8 {
 
         self.registers[reg_x] &= self.registers[reg_y];
         match self.target {
-            Target::Chip8 => self.registers[15] = 0,
-            Target::SuperChipLegacy | Target::SuperChip | Target::XoChip => {}
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 => self.registers[15] = 0,
+            Target::Chip48
+            | Target::SuperChip10
+            | Target::SuperChipLegacy
+            | Target::SuperChip
+            | Target::XoChip => {}
         }
 
         2
@@ -452,8 +595,12 @@ impl Chip8 {
 
         self.registers[reg_x] ^= self.registers[reg_y];
         match self.target {
-            Target::Chip8 => self.registers[15] = 0,
-            Target::SuperChipLegacy | Target::SuperChip | Target::XoChip => {}
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 => self.registers[15] = 0,
+            Target::Chip48
+            | Target::SuperChip10
+            | Target::SuperChipLegacy
+            | Target::SuperChip
+            | Target::XoChip => {}
         }
 
         2
@@ -488,8 +635,12 @@ impl Chip8 {
         let reg_x = Self::register_x(o);
 
         let val = match self.target {
-            Target::Chip8 | Target::XoChip => self.registers[Self::register_y(o)],
-            Target::SuperChipLegacy | Target::SuperChip => self.registers[reg_x],
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 | Target::XoChip => {
+                self.registers[Self::register_y(o)]
+            }
+            Target::Chip48 | Target::SuperChip10 | Target::SuperChipLegacy | Target::SuperChip => {
+                self.registers[reg_x]
+            }
         };
 
         self.registers[reg_x] = val.checked_shr(1).unwrap_or(0);
@@ -513,8 +664,12 @@ impl Chip8 {
         let reg_x = Self::register_x(o);
 
         let val = match self.target {
-            Target::Chip8 | Target::XoChip => self.registers[Self::register_y(o)],
-            Target::SuperChipLegacy | Target::SuperChip => self.registers[reg_x],
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 | Target::XoChip => {
+                self.registers[Self::register_y(o)]
+            }
+            Target::Chip48 | Target::SuperChip10 | Target::SuperChipLegacy | Target::SuperChip => {
+                self.registers[reg_x]
+            }
         };
 
         self.registers[reg_x] = val.checked_shl(1).unwrap_or(u8::MAX);
@@ -544,10 +699,13 @@ impl Chip8 {
         // BXNN - goto XNN + VX
         self.pc = (o & 0x0FFF)
             + match self.target {
-                Target::Chip8 | Target::XoChip => u16::from(self.registers[0]),
-                Target::SuperChipLegacy | Target::SuperChip => {
-                    u16::from(self.registers[Self::register_x(o)])
+                Target::Chip8 | Target::HiresChip8 | Target::Eti660 | Target::XoChip => {
+                    u16::from(self.registers[0])
                 }
+                Target::Chip48
+                | Target::SuperChip10
+                | Target::SuperChipLegacy
+                | Target::SuperChip => u16::from(self.registers[Self::register_x(o)]),
             };
     }
 
@@ -568,11 +726,11 @@ impl Chip8 {
         let data_count = o & 0x000F;
 
         // FIXME could cache in struct and change when switching hires mode
-        let (w, h) = if matches!(
+        let hires_capable = matches!(
             self.target,
-            Target::SuperChipLegacy | Target::SuperChip | Target::XoChip
-        ) && !self.hires
-        {
+            Target::SuperChip10 | Target::SuperChipLegacy | Target::SuperChip | Target::XoChip
+        );
+        let (w, h) = if hires_capable && !self.hires {
             (
                 self.profile.screen_width() / 2,
                 self.profile.screen_height() / 2,
@@ -581,13 +739,11 @@ impl Chip8 {
             (self.profile.screen_width(), self.profile.screen_height())
         };
 
-        let unset = if matches!(
-            self.target,
-            Target::SuperChipLegacy | Target::SuperChip | Target::XoChip
-        ) && self.hires
-            && data_count == 0
-        {
+        let unset = if hires_capable && self.hires && data_count == 0 {
             self.draw_sprite(16, self.registers[reg_x], self.registers[reg_y], w, h, 16)
+        } else if self.target == Target::SuperChip10 && data_count == 0 {
+            // SCHIP 1.0 draws 16 rows of 8 pixels in lores
+            self.draw_sprite(8, self.registers[reg_x], self.registers[reg_y], w, h, 16)
         } else {
             self.draw_sprite(
                 8,
@@ -601,6 +757,11 @@ impl Chip8 {
 
         self.draw = true;
         self.registers[15] = u8::from(unset);
+
+        // the display wait quirk: drawing waits for the next display interrupt,
+        // only in lores on the targets that have hires
+        self.vblank_wait = self.profile.lores_display_wait() && !self.hires;
+
         2
     }
 
@@ -681,18 +842,26 @@ impl Chip8 {
         // FX0A - Wait for a keypress and store the result in register VX
         let reg = Self::register_x(o);
 
-        match self.key_wait {
-            (Some(true), Some(key_num)) => {
-                self.key_wait = (None, None);
+        self.key_wait = match self.key_wait {
+            KeyWait::Done(key_num) => {
                 self.registers[reg] = key_num;
-                2
-            }
-            (None, None) => {
-                self.key_wait = (Some(false), None);
-                0
+                self.key_wait = KeyWait::Idle;
+                return 2;
             }
-            _ => 0,
-        }
+            KeyWait::Idle => match self.held_keys {
+                HeldKeys::Accept => self
+                    .key
+                    .first_one()
+                    .and_then(|key_num| u8::try_from(key_num).ok())
+                    .map_or(KeyWait::Press, KeyWait::Release),
+                HeldKeys::Ignore => KeyWait::Press,
+            },
+            wait => wait,
+        };
+
+        // the keypad is only looked at again after the next display interrupt
+        self.vblank_wait = true;
+        0
     }
 
     fn c8_timer_delay_store(&mut self, o: u16) -> u16 {
@@ -781,7 +950,11 @@ impl Chip8 {
             .copy_from_slice(&self.registers[0..=usize::from(reg_num)]);
 
         match self.target {
-            Target::Chip8 | Target::XoChip => self.i += reg_num + 1,
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 | Target::XoChip => {
+                self.i += reg_num + 1;
+            }
+            // the HP-48 interpreters before SCHIP 1.1 leave I one short
+            Target::Chip48 | Target::SuperChip10 => self.i += reg_num,
             Target::SuperChipLegacy | Target::SuperChip => {}
         }
 
@@ -796,7 +969,11 @@ impl Chip8 {
             .copy_from_slice(&self.memory[usize::from(self.i)..=usize::from(self.i + reg_num)]);
 
         match self.target {
-            Target::Chip8 | Target::XoChip => self.i += reg_num + 1,
+            Target::Chip8 | Target::HiresChip8 | Target::Eti660 | Target::XoChip => {
+                self.i += reg_num + 1;
+            }
+            // the HP-48 interpreters before SCHIP 1.1 leave I one short
+            Target::Chip48 | Target::SuperChip10 => self.i += reg_num,
             Target::SuperChipLegacy | Target::SuperChip => {}
         }
 
@@ -864,7 +1041,7 @@ impl Chip8 {
     fn sc_scroll_down(&mut self, o: u16) -> u16 {
         // 00CN
 
-        let num_of_rows = usize::from(o & 0xF);
+        let num_of_rows = self.scroll_distance(usize::from(o & 0xF));
         let width = usize::from(self.profile.screen_width());
         let height = usize::from(self.profile.screen_height());
 
@@ -898,15 +1075,17 @@ impl Chip8 {
     fn sc_scroll_right(&mut self) -> u16 {
         // 00FB
 
+        let distance = self.scroll_distance(4);
         let width = usize::from(self.profile.screen_width());
         let height = usize::from(self.profile.screen_height());
 
-        self.gfx.copy_within(0..((width * height) - 4), 4);
+        self.gfx
+            .copy_within(0..((width * height) - distance), distance);
 
         let mut offset: usize;
         for y in 0..height {
             offset = y * width;
-            self.gfx[offset..(offset + 4)].fill(false);
+            self.gfx[offset..(offset + distance)].fill(false);
         }
 
         self.draw = true;
@@ -917,15 +1096,16 @@ impl Chip8 {
     fn sc_scroll_left(&mut self) -> u16 {
         // 00FC
 
+        let distance = self.scroll_distance(4);
         let width = usize::from(self.profile.screen_width());
         let height = usize::from(self.profile.screen_height());
 
-        self.gfx.copy_within(4..(width * height), 0);
+        self.gfx.copy_within(distance..(width * height), 0);
 
         let mut offset: usize;
         for y in 0..height {
             offset = (y + 1) * width;
-            self.gfx[(offset - 4)..offset].fill(false);
+            self.gfx[(offset - distance)..offset].fill(false);
         }
 
         self.draw = true;
@@ -933,6 +1113,18 @@ impl Chip8 {
         2
     }
 
+    /// The pixels moved by a scroll of `distance`. SCHIP 1.0 scrolls by hires
+    /// pixels even in lores, so only half as far there. An odd distance would
+    /// leave the display half a lores pixel out, which the lores buffer can't
+    /// hold, so the extra half pixel is dropped.
+    fn scroll_distance(&self, distance: usize) -> usize {
+        if self.target == Target::SuperChip10 && !self.hires {
+            distance / 2
+        } else {
+            distance
+        }
+    }
+
     fn register_x(o: u16) -> usize {
         usize::from((o & 0x0F00).wrapping_shr(8))
     }
@@ -966,14 +1158,135 @@ mod tests {
     use bitvec::prelude::Lsb0;
     use rand::rngs::SmallRng;
     use rand::SeedableRng;
-    use sdl2::keyboard::Mod;
-    use sdl2::keyboard::Scancode;
 
     use super::Chip8;
+    use super::HeldKeys;
     use crate::profile;
     use crate::Action;
     use crate::Target;
 
+    #[test]
+    fn test_draw_waits_for_vblank() -> Result<(), Error> {
+        // when
+        let chip8 = |target| -> Result<Chip8, Error> {
+            let mut chip8 = Chip8::new(
+                target,
+                *profile::profiles()
+                    .get(&target)
+                    .ok_or(anyhow!("Unknown profile"))?,
+                Box::new(rand::rng()),
+            );
+            // 00E0: clear, D005: draw, 00FF: hires, D005: draw
+            chip8.load_rom(&[0x00, 0xE0, 0xD0, 0x05, 0x00, 0xFF, 0xD0, 0x05])?;
+            Ok(chip8)
+        };
+        let mut original = chip8(Target::Chip8)?;
+        let mut legacy = chip8(Target::SuperChipLegacy)?;
+        let mut modern = chip8(Target::SuperChip)?;
+
+        // then
+        original.emulate_cycle();
+        let after_clear = original.waiting_for_vblank();
+        original.emulate_cycle();
+        let after_draw = original.waiting_for_vblank();
+        original.vblank();
+        let after_vblank = original.waiting_for_vblank();
+
+        let mut legacy_waits = Vec::new();
+        for _ in 0..4 {
+            legacy.emulate_cycle();
+            legacy_waits.push(legacy.waiting_for_vblank());
+            legacy.vblank();
+        }
+
+        modern.emulate_cycle();
+        modern.emulate_cycle();
+
+        // verify
+        assert!(!after_clear);
+        assert!(after_draw);
+        assert!(!after_vblank);
+        assert_eq!(legacy_waits, vec![false, true, false, false]);
+        assert!(!modern.waiting_for_vblank());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_wait_press_then_release() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Chip8,
+            *profile::profiles()
+                .get(&Target::Chip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        // F30A: wait for a key into V3
+        chip8.load_rom(&[0xF3, 0x0A])?;
+
+        // then
+        let frame = |chip8: &mut Chip8| {
+            chip8.emulate_cycle();
+            let state = (chip8.waiting_for_vblank(), chip8.audio_sound());
+            chip8.vblank();
+            state
+        };
+        let waiting = frame(&mut chip8);
+        chip8.press_key(0x7, true);
+        let held = frame(&mut chip8);
+        chip8.press_key(0x7, false);
+        chip8.emulate_cycle();
+
+        // verify
+        assert_eq!(waiting, (true, false));
+        assert_eq!(held, (true, true));
+        assert_eq!(chip8.registers[0x3], 0x7);
+        assert_eq!(chip8.pc, 0x202);
+        assert!(!chip8.audio_sound());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_wait_held_keys() -> Result<(), Error> {
+        // when
+        let chip8 = |held_keys| -> Result<Chip8, Error> {
+            let mut chip8 = Chip8::new(
+                Target::Chip8,
+                *profile::profiles()
+                    .get(&Target::Chip8)
+                    .ok_or(anyhow!("Unknown profile"))?,
+                Box::new(rand::rng()),
+            );
+            chip8.set_held_keys(held_keys);
+            // F30A: wait for a key into V3
+            chip8.load_rom(&[0xF3, 0x0A])?;
+            chip8.press_key(0x5, true);
+            Ok(chip8)
+        };
+        let mut ignore = chip8(HeldKeys::Ignore)?;
+        let mut accept = chip8(HeldKeys::Accept)?;
+        let mut reasserted = chip8(HeldKeys::Ignore)?;
+
+        // then
+        for chip8 in [&mut ignore, &mut accept] {
+            chip8.emulate_cycle();
+            chip8.press_key(0x5, false);
+            chip8.emulate_cycle();
+        }
+        // the held key is set down again, as the gym does every step
+        reasserted.emulate_cycle();
+        reasserted.press_key(0x5, true);
+        reasserted.press_key(0x5, false);
+        reasserted.emulate_cycle();
+
+        // verify
+        assert_eq!(ignore.pc, 0x200);
+        assert_eq!(reasserted.pc, 0x200);
+        assert_eq!(accept.pc, 0x202);
+        assert_eq!(accept.registers[0x3], 0x5);
+        Ok(())
+    }
+
     #[test]
     fn test_graphics_needs_refresh() -> Result<(), Error> {
         // when
@@ -1088,7 +1401,7 @@ mod tests {
         chip8.sound_timer = 10;
 
         // then
-        chip8.update_timers();
+        chip8.vblank();
 
         // verify
         assert_eq!(chip8.delay_timer, 14);
@@ -1110,7 +1423,7 @@ mod tests {
         chip8.sound_timer = 0;
 
         // then
-        chip8.update_timers();
+        chip8.vblank();
 
         // verify
         assert_eq!(chip8.delay_timer, 0);
@@ -1119,26 +1432,7 @@ mod tests {
     }
 
     #[test]
-    fn test_quit_event_returns_quit_action() -> Result<(), Error> {
-        // when
-        let mut chip8 = Chip8::new(
-            Target::Chip8,
-            *profile::profiles()
-                .get(&Target::Chip8)
-                .ok_or(anyhow!("Unknown profile"))?,
-            Box::new(rand::rng()),
-        );
-
-        // then
-        let result = chip8.handle_key(&sdl2::event::Event::Quit { timestamp: 0 })?;
-
-        // verify
-        assert_eq!(result, Some(Action::Quit));
-        Ok(())
-    }
-
-    #[test]
-    fn test_keydown_escape_returns_quit_action() -> Result<(), Error> {
+    fn test_key_presses_store_pressed_state() -> Result<(), Error> {
         // when
         let mut chip8 = Chip8::new(
             Target::Chip8,
@@ -1149,56 +1443,19 @@ mod tests {
         );
 
         // then
-        let result = chip8.handle_key(&sdl2::event::Event::KeyDown {
-            timestamp: 0,
-            window_id: 0,
-            scancode: Some(Scancode::Escape),
-            keycode: None,
-            keymod: Mod::empty(),
-            repeat: false,
-        })?;
-
-        // verify
-        assert_eq!(result, Some(Action::Quit));
-        Ok(())
-    }
-
-    #[test]
-    fn test_other_key_events_store_pressed_state() -> Result<(), Error> {
-        // when
-        let mut chip8 = Chip8::new(
-            Target::Chip8,
-            *profile::profiles()
-                .get(&Target::Chip8)
-                .ok_or(anyhow!("Unknown profile"))?,
-            Box::new(rand::rng()),
-        );
-
-        // then
-        let results: Vec<_> = Chip8::key_scan_mapping()
-            .keys()
-            .map(|scancode| {
-                chip8.handle_key(&sdl2::event::Event::KeyDown {
-                    timestamp: 0,
-                    window_id: 0,
-                    scancode: Some(*scancode),
-                    keycode: None,
-                    keymod: Mod::empty(),
-                    repeat: false,
-                })
-            })
-            .collect();
+        for key_num in 0u8..16 {
+            chip8.press_key(key_num, true);
+        }
 
         // verify
         let mut k = BitArray::<u16>::ZERO;
         k.fill(true);
         assert_eq!(chip8.key, k);
-        assert!(results.into_iter().all(|r| r.is_ok_and(|o| o.is_none())));
         Ok(())
     }
 
     #[test]
-    fn test_other_key_events_store_released_state() -> Result<(), Error> {
+    fn test_key_releases_store_released_state() -> Result<(), Error> {
         // when
         let mut chip8 = Chip8::new(
             Target::Chip8,
@@ -1210,23 +1467,12 @@ mod tests {
         chip8.key.fill(true);
 
         // then
-        let results: Vec<_> = Chip8::key_scan_mapping()
-            .keys()
-            .map(|scancode| {
-                chip8.handle_key(&sdl2::event::Event::KeyUp {
-                    timestamp: 0,
-                    window_id: 0,
-                    scancode: Some(*scancode),
-                    keycode: None,
-                    keymod: Mod::empty(),
-                    repeat: false,
-                })
-            })
-            .collect();
+        for key_num in 0u8..16 {
+            chip8.press_key(key_num, false);
+        }
 
         // verify
         assert_eq!(chip8.key, BitArray::<u16>::ZERO);
-        assert!(results.into_iter().all(|r| r.is_ok_and(|o| o.is_none())));
         Ok(())
     }
 
@@ -2308,6 +2554,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_next_writes() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Chip8,
+            *profile::profiles()
+                .get(&Target::Chip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.i = 0x300;
+        chip8.memory[0x200..0x206].copy_from_slice(&[0xF2, 0x33, 0xF3, 0x55, 0xF3, 0x65]);
+
+        // then
+        let bcd = chip8.next_writes();
+        chip8.pc = 0x202;
+        let dump = chip8.next_writes();
+        chip8.pc = 0x204;
+        let load = chip8.next_writes();
+
+        // verify
+        assert_eq!(bcd, Some(0x300..=0x302));
+        assert_eq!(dump, Some(0x300..=0x303));
+        assert_eq!(load, None);
+        Ok(())
+    }
+
     #[test]
     fn test_sc_mem_reg_dump() -> Result<(), Error> {
         // when
@@ -2394,6 +2667,211 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_chip48_mem_reg_dump() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Chip48,
+            *profile::profiles()
+                .get(&Target::Chip48)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.i = 0xC60;
+        chip8.memory[0x200] = 0xF3;
+        chip8.memory[0x201] = 0x55;
+
+        // then
+        chip8.emulate_cycle();
+
+        // verify
+        assert_eq!(chip8.i, 0xC63);
+        assert_eq!(chip8.pc, 0x202);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chip48_flow_jump() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Chip48,
+            *profile::profiles()
+                .get(&Target::Chip48)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.memory[0x200] = 0xB8;
+        chip8.memory[0x201] = 0xB3;
+        chip8.registers[0] = 0x55;
+        chip8.registers[8] = 0x11;
+
+        // then
+        chip8.emulate_cycle();
+
+        // verify
+        assert_eq!(chip8.pc, 0x8C4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sc10_draw_sprite_lores_dxy0() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::SuperChip10,
+            *profile::profiles()
+                .get(&Target::SuperChip10)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.i = 0x300;
+        chip8.memory[0x300..0x310].fill(0xFF);
+        chip8.memory[0x200] = 0xD0;
+        chip8.memory[0x201] = 0x00;
+
+        // then
+        chip8.emulate_cycle();
+
+        // verify
+        let width = usize::from(chip8.profile.screen_width());
+        let row = |y: usize| chip8.gfx[y * width..y * width + 9].count_ones();
+        assert_eq!(row(0), 8);
+        assert_eq!(row(15), 8);
+        assert_eq!(row(16), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sc10_scroll_lores() -> Result<(), Error> {
+        // when
+        let chip8 = |target| -> Result<Chip8, Error> {
+            let mut chip8 = Chip8::new(
+                target,
+                *profile::profiles()
+                    .get(&target)
+                    .ok_or(anyhow!("Unknown profile"))?,
+                Box::new(rand::rng()),
+            );
+            // 00C4: scroll down 4, 00FB: scroll right
+            chip8.load_rom(&[0x00, 0xC4, 0x00, 0xFB])?;
+            chip8.gfx.set(0, true);
+            Ok(chip8)
+        };
+        let mut legacy = chip8(Target::SuperChipLegacy)?;
+        let mut original = chip8(Target::SuperChip10)?;
+
+        // then
+        for chip8 in [&mut legacy, &mut original] {
+            chip8.emulate_cycle();
+            chip8.emulate_cycle();
+        }
+
+        // verify
+        assert_eq!(legacy.gfx.first_one(), Some(4 * 128 + 4));
+        assert_eq!(original.gfx.first_one(), Some(2 * 128 + 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sc10_scroll_lores_odd() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::SuperChip10,
+            *profile::profiles()
+                .get(&Target::SuperChip10)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        // 00C3: scroll down 3, 00C1: scroll down 1
+        chip8.load_rom(&[0x00, 0xC3, 0x00, 0xC1])?;
+        chip8.gfx.set(0, true);
+
+        // then
+        chip8.emulate_cycle();
+        let after_three = chip8.gfx.first_one();
+        chip8.emulate_cycle();
+
+        // verify
+        assert_eq!(after_three, Some(128));
+        assert_eq!(chip8.gfx.first_one(), Some(128));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_and_start_address() -> Result<(), Error> {
+        // when
+        let chip8 = |target| -> Result<Chip8, Error> {
+            let mut chip8 = Chip8::new(
+                target,
+                *profile::profiles()
+                    .get(&target)
+                    .ok_or(anyhow!("Unknown profile"))?,
+                Box::new(rand::rng()),
+            );
+            chip8.load_rom(&[0x12, 0x60])?;
+            Ok(chip8)
+        };
+
+        // then
+        let hires = chip8(Target::HiresChip8)?;
+        let eti = chip8(Target::Eti660)?;
+
+        // verify
+        assert_eq!(hires.memory[0x200..0x202], [0x12, 0x60]);
+        assert_eq!(hires.pc, 0x2C0);
+        assert_eq!(eti.memory[0x600..0x602], [0x12, 0x60]);
+        assert_eq!(eti.memory[0x200], 0);
+        assert_eq!(eti.pc, 0x600);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rom_too_large() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Eti660,
+            *profile::profiles()
+                .get(&Target::Eti660)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+
+        // then
+        let fits = chip8.load_rom(&[0; 2560]);
+        let too_large = chip8.load_rom(&[0; 2561]);
+
+        // verify
+        assert!(fits.is_ok());
+        assert_eq!(
+            too_large.map_err(|e| e.to_string()),
+            Err("ROM too large for eti-660 (2561 bytes, 2560 fit)".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hires_chip8_display_clear() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::HiresChip8,
+            *profile::profiles()
+                .get(&Target::HiresChip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.gfx.fill(true);
+        chip8.memory[0x2C0] = 0x02;
+        chip8.memory[0x2C1] = 0x30;
+
+        // then
+        chip8.emulate_cycle();
+
+        // verify
+        assert_eq!(chip8.gfx.len(), 64 * 64);
+        assert!(chip8.gfx.not_any());
+        assert_eq!(chip8.pc, 0x2C2);
+        Ok(())
+    }
+
     #[test]
     fn test_unknown_opcode_panics() -> Result<(), Error> {
         let result = catch_unwind_silent(|| {
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use sdl2::controller::Axis;
+use sdl2::controller::Button;
+use sdl2::controller::GameController;
+use sdl2::event::Event;
+use sdl2::keyboard::Mod;
+use sdl2::GameControllerSubsystem;
+
+use crate::hardware;
+
+// stick values inside this range are treated as centred
+const AXIS_DEADZONE: i16 = 8_000;
+
+const BUTTON_NAMES: [(&str, Button); 15] = [
+    ("a", Button::A),
+    ("b", Button::B),
+    ("x", Button::X),
+    ("y", Button::Y),
+    ("back", Button::Back),
+    ("guide", Button::Guide),
+    ("start", Button::Start),
+    ("leftstick", Button::LeftStick),
+    ("rightstick", Button::RightStick),
+    ("leftshoulder", Button::LeftShoulder),
+    ("rightshoulder", Button::RightShoulder),
+    ("dpup", Button::DPadUp),
+    ("dpdown", Button::DPadDown),
+    ("dpleft", Button::DPadLeft),
+    ("dpright", Button::DPadRight),
+];
+
+const AXIS_NAMES: [(&str, Axis); 6] = [
+    ("leftx", Axis::LeftX),
+    ("lefty", Axis::LeftY),
+    ("rightx", Axis::RightX),
+    ("righty", Axis::RightY),
+    ("lefttrigger", Axis::TriggerLeft),
+    ("righttrigger", Axis::TriggerRight),
+];
+
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+enum Direction {
+    Negative,
+    Positive,
+}
+
+/// Maps game controller buttons and stick directions to CHIP-8 keys.
+///
+/// Files are plain text, one `control = key` pair per line, using the SDL
+/// controller names (`a`, `dpup`, `leftshoulder`, ...) for buttons and an
+/// axis name with a `-` or `+` suffix (`leftx-`, `righty+`, ...) for sticks
+/// and triggers. Keys are single hex digits. Blank lines and lines starting
+/// with `#` are ignored.
+#[must_use]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Mapping {
+    buttons: HashMap<Button, u8>,
+    axes: HashMap<(Axis, Direction), u8>,
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        // D-pad and left stick on the 2/4/6/8 cross used by most games,
+        // face buttons on the remaining commonly used keys
+        Self {
+            buttons: HashMap::from([
+                (Button::DPadUp, 0x2),
+                (Button::DPadDown, 0x8),
+                (Button::DPadLeft, 0x4),
+                (Button::DPadRight, 0x6),
+                (Button::A, 0x5),
+                (Button::B, 0x0),
+                (Button::X, 0x7),
+                (Button::Y, 0x9),
+                (Button::LeftShoulder, 0x1),
+                (Button::RightShoulder, 0x3),
+                (Button::Back, 0xa),
+                (Button::Start, 0xf),
+            ]),
+            axes: HashMap::from([
+                ((Axis::LeftX, Direction::Negative), 0x4),
+                ((Axis::LeftX, Direction::Positive), 0x6),
+                ((Axis::LeftY, Direction::Negative), 0x2),
+                ((Axis::LeftY, Direction::Positive), 0x8),
+            ]),
+        }
+    }
+}
+
+impl Mapping {
+    /// Loads the mapping from `path` if given, otherwise from a per-ROM
+    /// `<rom>.controller` file next to the ROM, falling back to the default.
+    pub(crate) fn load(path: Option<&str>, rom_filename: &str) -> Result<Self> {
+        if let Some(p) = path {
+            return Self::from_file(Path::new(p));
+        }
+
+        let per_rom = Path::new(rom_filename).with_extension("controller");
+        if per_rom.is_file() {
+            return Self::from_file(&per_rom);
+        }
+
+        Ok(Self::default())
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read controller mapping {}", path.display()))?;
+
+        Self::parse(&contents)
+            .with_context(|| format!("Invalid controller mapping {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut mapping = Self {
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+        };
+
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (control, key) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected 'control = key'", num + 1))?;
+            let (control, key) = (control.trim(), key.trim());
+
+            let key_num = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|k| *k <= 0xF)
+                .ok_or_else(|| anyhow!("line {}: invalid key '{key}'", num + 1))?;
+
+            if let Some(button) = Self::button_from_name(control) {
+                mapping.buttons.insert(button, key_num);
+            } else if let Some(axis_dir) = Self::axis_from_name(control) {
+                mapping.axes.insert(axis_dir, key_num);
+            } else {
+                return Err(anyhow!("line {}: unknown control '{control}'", num + 1));
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    fn button_from_name(name: &str) -> Option<Button> {
+        BUTTON_NAMES
+            .iter()
+            .find_map(|(n, b)| (*n == name).then_some(*b))
+    }
+
+    fn axis_from_name(name: &str) -> Option<(Axis, Direction)> {
+        let (axis_name, direction) = if let Some(a) = name.strip_suffix('-') {
+            (a, Direction::Negative)
+        } else if let Some(a) = name.strip_suffix('+') {
+            (a, Direction::Positive)
+        } else {
+            return None;
+        };
+
+        AXIS_NAMES
+            .iter()
+            .find_map(|(n, a)| (*n == axis_name).then_some((*a, direction)))
+    }
+}
+
+#[must_use]
+pub(super) struct Controllers {
+    subsystem: GameControllerSubsystem,
+    open: HashMap<u32, GameController>,
+    mapping: Mapping,
+    button_state: HashSet<(u32, Button)>,
+    axis_state: HashMap<(u32, Axis), Direction>,
+}
+
+impl Controllers {
+    pub(super) fn new(subsystem: GameControllerSubsystem, mapping: Mapping) -> Self {
+        // controllers already attached at startup arrive as ControllerDeviceAdded
+        // events, so opening is left entirely to the hot-plug path
+        Self {
+            subsystem,
+            open: HashMap::new(),
+            mapping,
+            button_state: HashSet::new(),
+            axis_state: HashMap::new(),
+        }
+    }
+
+    /// Converts controller events into the equivalent keyboard events, opening
+    /// and closing controllers as they are plugged in and removed. Other events
+    /// are passed through unchanged.
+    pub(super) fn translate(&mut self, event: Event) -> Vec<Event> {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.subsystem.open(which) {
+                    Ok(c) => {
+                        self.open.insert(c.instance_id(), c);
+                    }
+                    Err(e) => eprintln!("unable to open controller {which}: {e}"),
+                }
+                vec![]
+            }
+            Event::ControllerDeviceRemoved { timestamp, which } => {
+                self.open.remove(&which);
+                self.release_all(timestamp, which)
+            }
+            Event::ControllerButtonDown {
+                timestamp,
+                which,
+                button,
+            } => {
+                self.button_state.insert((which, button));
+                self.button_event(timestamp, button, true)
+            }
+            Event::ControllerButtonUp {
+                timestamp,
+                which,
+                button,
+            } => {
+                self.button_state.remove(&(which, button));
+                self.button_event(timestamp, button, false)
+            }
+            Event::ControllerAxisMotion {
+                timestamp,
+                which,
+                axis,
+                value,
+            } => self.translate_axis(timestamp, which, axis, value),
+            e => vec![e],
+        }
+    }
+
+    fn button_event(&self, timestamp: u32, button: Button, pressed: bool) -> Vec<Event> {
+        self.mapping
+            .buttons
+            .get(&button)
+            .and_then(|k| Self::key_event(timestamp, *k, pressed))
+            .into_iter()
+            .collect()
+    }
+
+    /// Releases every key a removed controller was holding, so that pulling
+    /// it out mid-press doesn't leave the key stuck down.
+    fn release_all(&mut self, timestamp: u32, which: u32) -> Vec<Event> {
+        let mut held = vec![];
+
+        self.button_state.retain(|(id, button)| {
+            if *id == which {
+                held.extend(self.mapping.buttons.get(button).copied());
+            }
+            *id != which
+        });
+        self.axis_state.retain(|(id, axis), direction| {
+            if *id == which {
+                held.extend(self.mapping.axes.get(&(*axis, *direction)).copied());
+            }
+            *id != which
+        });
+
+        held.sort_unstable();
+        held.dedup();
+        held.into_iter()
+            .filter_map(|k| Self::key_event(timestamp, k, false))
+            .collect()
+    }
+
+    fn translate_axis(&mut self, timestamp: u32, which: u32, axis: Axis, value: i16) -> Vec<Event> {
+        let direction = match value {
+            v if v < -AXIS_DEADZONE => Some(Direction::Negative),
+            v if v > AXIS_DEADZONE => Some(Direction::Positive),
+            _ => None,
+        };
+
+        let previous = self.axis_state.get(&(which, axis)).copied();
+        if previous == direction {
+            return vec![];
+        }
+
+        match direction {
+            Some(d) => self.axis_state.insert((which, axis), d),
+            None => self.axis_state.remove(&(which, axis)),
+        };
+
+        let release = previous
+            .and_then(|d| self.mapping.axes.get(&(axis, d)))
+            .and_then(|k| Self::key_event(timestamp, *k, false));
+        let press = direction
+            .and_then(|d| self.mapping.axes.get(&(axis, d)))
+            .and_then(|k| Self::key_event(timestamp, *k, true));
+
+        release.into_iter().chain(press).collect()
+    }
+
+    fn key_event(timestamp: u32, key_num: u8, pressed: bool) -> Option<Event> {
+        let scancode = hardware::key_scancode(key_num)?;
+
+        Some(if pressed {
+            Event::KeyDown {
+                timestamp,
+                window_id: 0,
+                keycode: None,
+                scancode: Some(scancode),
+                keymod: Mod::empty(),
+                repeat: false,
+            }
+        } else {
+            Event::KeyUp {
+                timestamp,
+                window_id: 0,
+                keycode: None,
+                scancode: Some(scancode),
+                keymod: Mod::empty(),
+                repeat: false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use sdl2::controller::Axis;
+    use sdl2::controller::Button;
+
+    use super::Direction;
+    use super::Mapping;
+
+    #[test]
+    fn test_parse_mapping() -> Result<(), Error> {
+        // when
+        let contents = "# tetris\n\ndpleft = 5\na=4\nleftx+ = 6\n";
+
+        // then
+        let mapping = Mapping::parse(contents)?;
+
+        // verify
+        assert_eq!(mapping.buttons.get(&Button::DPadLeft), Some(&0x5));
+        assert_eq!(mapping.buttons.get(&Button::A), Some(&0x4));
+        assert_eq!(
+            mapping.axes.get(&(Axis::LeftX, Direction::Positive)),
+            Some(&0x6)
+        );
+        assert_eq!(mapping.buttons.len(), 2);
+        assert_eq!(mapping.axes.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mapping_invalid() {
+        assert!(Mapping::parse("dpup").is_err());
+        assert!(Mapping::parse("dpup = 10").is_err());
+        assert!(Mapping::parse("turbo = 1").is_err());
+        assert!(Mapping::parse("leftx = 1").is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! Compares a run against a reference trace from another emulator.
+//!
+//! A reference trace is a text file with one line per executed instruction,
+//! giving the machine state after that instruction as whitespace-separated
+//! `NAME=VALUE` fields with hex values, e.g.
+//!
+//! ```text
+//! PC=0204 OP=600C I=022A V0=0C V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00
+//! ```
+//!
+//! `PC` is the address of the next instruction, and `OP` the opcode that was
+//! executed. The other names are `I`, `V0`-`VF`, `DT`, `ST` and `SP`. Only the
+//! fields given are compared, any others are ignored, and names are not case
+//! sensitive. Blank lines and lines starting with `#` are skipped.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::disassembler;
+use crate::emulator::Captures;
+use crate::emulator::Emulator;
+use crate::emulator::Observer;
+use crate::emulator::Step;
+use crate::headless;
+use crate::headless::Headless;
+use crate::palette;
+use crate::Action;
+use crate::ProcessType;
+use crate::Target;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Field {
+    Pc,
+    Op,
+    I,
+    V(u8),
+    Dt,
+    St,
+    Sp,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+
+        match name.as_str() {
+            "PC" => Some(Self::Pc),
+            "OP" => Some(Self::Op),
+            "I" => Some(Self::I),
+            "DT" => Some(Self::Dt),
+            "ST" => Some(Self::St),
+            "SP" => Some(Self::Sp),
+            _ => name
+                .strip_prefix('V')
+                .filter(|r| r.len() == 1)
+                .and_then(|r| u8::from_str_radix(r, 16).ok())
+                .map(Self::V),
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Self::Pc => "PC".into(),
+            Self::Op => "OP".into(),
+            Self::I => "I".into(),
+            Self::V(x) => format!("V{x:X}"),
+            Self::Dt => "DT".into(),
+            Self::St => "ST".into(),
+            Self::Sp => "SP".into(),
+        }
+    }
+
+    fn format(self, value: u16) -> String {
+        match self {
+            Self::Pc | Self::Op | Self::I => format!("{value:04X}"),
+            _ => format!("{value:02X}"),
+        }
+    }
+
+    fn value(self, step: &Step) -> u16 {
+        let after = &step.after;
+
+        match self {
+            Self::Pc => after.pc,
+            Self::Op => step.opcode,
+            Self::I => after.i,
+            Self::V(x) => u16::from(after.registers[usize::from(x)]),
+            Self::Dt => u16::from(after.delay_timer),
+            Self::St => u16::from(after.sound_timer),
+            Self::Sp => u16::from(after.sp),
+        }
+    }
+}
+
+/// The state given by one line of the reference trace.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Expected {
+    line: usize,
+    fields: Vec<(Field, u16)>,
+}
+
+fn parse_reference(contents: &str) -> Result<Vec<Expected>> {
+    let mut expected = Vec::new();
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = Vec::new();
+        for token in line.split_whitespace() {
+            let (name, value) = token
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected NAME=VALUE, got '{token}'", num + 1))?;
+
+            if let Some(field) = Field::parse(name) {
+                let value = headless::parse_address(value)
+                    .with_context(|| format!("line {}: invalid {name}", num + 1))?;
+                fields.push((field, value));
+            }
+        }
+
+        expected.push(Expected {
+            line: num + 1,
+            fields,
+        });
+    }
+
+    Ok(expected)
+}
+
+/// Checks each instruction against the reference, stopping at the first
+/// divergence or when the reference runs out.
+struct Checker {
+    expected: Vec<Expected>,
+    next: usize,
+    previous: Option<String>,
+    divergence: Option<String>,
+}
+
+impl Checker {
+    fn describe(step: &Step) -> String {
+        format!(
+            "{:04X} {:04X} {}",
+            step.before.pc,
+            step.opcode,
+            disassembler::disassemble(step.opcode)
+        )
+    }
+
+    fn report(&self, expected: &Expected, step: &Step) -> String {
+        let mut report = format!(
+            "instruction {} (frame {}, reference line {}) diverges:\n",
+            self.next + 1,
+            step.frame,
+            expected.line
+        );
+        if let Some(previous) = &self.previous {
+            let _ = writeln!(report, "  after   {previous}");
+        }
+        let _ = writeln!(report, "  ran     {}\n", Self::describe(step));
+        report.push_str("        chipper  reference\n");
+
+        for (field, value) in &expected.fields {
+            let actual = field.value(step);
+            let row = format!(
+                "  {:<4}  {:<7}  {:<9}{}",
+                field.name(),
+                field.format(actual),
+                field.format(*value),
+                if actual == *value { "" } else { "<<" }
+            );
+            let _ = writeln!(report, "{}", row.trim_end());
+        }
+
+        report
+    }
+}
+
+impl Observer for Checker {
+    fn instruction(&mut self, step: &Step) -> Result<Option<Action>> {
+        let Some(expected) = self.expected.get(self.next) else {
+            return Ok(Some(Action::Quit));
+        };
+
+        if expected.fields.iter().any(|(f, v)| f.value(step) != *v) {
+            self.divergence = Some(self.report(expected, step));
+            return Ok(Some(Action::Quit));
+        }
+
+        self.next += 1;
+        self.previous = Some(Self::describe(step));
+
+        Ok((self.next == self.expected.len()).then_some(Action::Quit))
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        if let Some(divergence) = self.divergence {
+            print!("{divergence}");
+            return Err(anyhow!("Traces diverge"));
+        }
+
+        if self.next < self.expected.len() {
+            return Err(anyhow!(
+                "The run ended after {} instructions, but the reference has {}",
+                self.next,
+                self.expected.len()
+            ));
+        }
+
+        println!("all {} instructions match the reference", self.next);
+        Ok(())
+    }
+}
+
+/// Runs `rom` with a seeded random number generator and scripted key events,
+/// comparing the state after each instruction with the reference trace.
+pub(super) fn run(
+    target: Target,
+    rom: &str,
+    reference: &Path,
+    seed: u64,
+    keys: Option<&Path>,
+) -> Result<()> {
+    let contents = fs::read_to_string(reference)
+        .with_context(|| format!("Unable to read reference trace {}", reference.display()))?;
+    let expected = parse_reference(&contents)
+        .with_context(|| format!("Invalid reference trace {}", reference.display()))?;
+
+    let events = match keys {
+        Some(path) => {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Unable to read key events {}", path.display()))?;
+            headless::parse_key_events(&contents)
+                .with_context(|| format!("Invalid key events {}", path.display()))?
+        }
+        None => Vec::new(),
+    };
+
+    let checker = Checker {
+        expected,
+        next: 0,
+        previous: None,
+        divergence: None,
+    };
+
+    let frontend = Headless::new(palette::Preset::Default.palette(), events);
+    Emulator::new(target, frontend, Captures::default())?
+        .with_seed(seed)
+        .with_observer(Box::new(checker))
+        .unthrottled()
+        .process(ProcessType::Run, rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use super::parse_reference;
+    use super::Checker;
+    use super::Expected;
+    use super::Field;
+    use crate::chip8;
+    use crate::emulator::Observer;
+    use crate::emulator::Step;
+    use crate::Action;
+
+    fn step(pc: u16, opcode: u16, registers: [u8; 16]) -> Step {
+        let state = chip8::State {
+            registers: [0; 16],
+            i: 0,
+            pc,
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+
+        Step {
+            cycle: 0,
+            frame: 0,
+            opcode,
+            before: state,
+            after: chip8::State {
+                registers,
+                pc: pc + 2,
+                ..state
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_reference() -> Result<(), Error> {
+        // when
+        let contents = "# from another emulator\n\npc=0202 V0=0C cycle=1 vf=01\n";
+
+        // then
+        let expected = parse_reference(contents)?;
+
+        // verify
+        assert_eq!(
+            expected,
+            vec![Expected {
+                line: 3,
+                fields: vec![(Field::Pc, 0x202), (Field::V(0), 0x0C), (Field::V(0xF), 1)],
+            }]
+        );
+        assert!(parse_reference("PC 0202").is_err());
+        assert!(parse_reference("PC=ZZ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checker_stops_at_first_divergence() -> Result<(), Error> {
+        // when
+        let mut checker = Checker {
+            expected: parse_reference("PC=0202 V0=0C\nPC=0204 VF=00\nPC=0206\n")?,
+            next: 0,
+            previous: None,
+            divergence: None,
+        };
+        let mut registers = [0; 16];
+        registers[0] = 0x0C;
+
+        // then
+        let first = checker.instruction(&step(0x200, 0x600C, registers))?;
+        registers[0xF] = 1;
+        let second = checker.instruction(&step(0x202, 0x8015, registers))?;
+
+        // verify
+        assert_eq!(first, None);
+        assert_eq!(second, Some(Action::Quit));
+        let report = checker.divergence.unwrap_or_default();
+        assert!(report.starts_with("instruction 2 (frame 0, reference line 2) diverges"));
+        assert!(report.contains("  VF    01       00       <<"));
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+/// Disassembles an opcode into Cowgod-style assembly, with the SUPER-CHIP and
+/// XO-CHIP extensions. Opcodes that no target implements are shown as data.
+#[must_use]
+pub(crate) fn disassemble(opcode: u16) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    match opcode {
+        0x00E0 => "CLS".into(),
+        0x00EE => "RET".into(),
+        0x00FB => "SCR".into(),
+        0x00FC => "SCL".into(),
+        0x00FD => "EXIT".into(),
+        0x00FE => "LOW".into(),
+        0x00FF => "HIGH".into(),
+        o if o & 0xFFF0 == 0x00C0 => format!("SCD {n:#X}"),
+        o if o & 0xFFF0 == 0x00D0 => format!("SCU {n:#X}"),
+        o if o & 0xF000 == 0x0000 => format!("SYS {nnn:#05X}"),
+        o if o & 0xF000 == 0x1000 => format!("JP {nnn:#05X}"),
+        o if o & 0xF000 == 0x2000 => format!("CALL {nnn:#05X}"),
+        o if o & 0xF000 == 0x3000 => format!("SE V{x:X}, {nn:#04X}"),
+        o if o & 0xF000 == 0x4000 => format!("SNE V{x:X}, {nn:#04X}"),
+        o if o & 0xF00F == 0x5000 => format!("SE V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x5002 => format!("LD [I], V{x:X}-V{y:X}"),
+        o if o & 0xF00F == 0x5003 => format!("LD V{x:X}-V{y:X}, [I]"),
+        o if o & 0xF000 == 0x6000 => format!("LD V{x:X}, {nn:#04X}"),
+        o if o & 0xF000 == 0x7000 => format!("ADD V{x:X}, {nn:#04X}"),
+        o if o & 0xF00F == 0x8000 => format!("LD V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8001 => format!("OR V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8002 => format!("AND V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8003 => format!("XOR V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8004 => format!("ADD V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8005 => format!("SUB V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8006 => format!("SHR V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x8007 => format!("SUBN V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x800E => format!("SHL V{x:X}, V{y:X}"),
+        o if o & 0xF00F == 0x9000 => format!("SNE V{x:X}, V{y:X}"),
+        o if o & 0xF000 == 0xA000 => format!("LD I, {nnn:#05X}"),
+        o if o & 0xF000 == 0xB000 => format!("JP V0, {nnn:#05X}"),
+        o if o & 0xF000 == 0xC000 => format!("RND V{x:X}, {nn:#04X}"),
+        o if o & 0xF000 == 0xD000 => format!("DRW V{x:X}, V{y:X}, {n:#X}"),
+        o if o & 0xF0FF == 0xE09E => format!("SKP V{x:X}"),
+        o if o & 0xF0FF == 0xE0A1 => format!("SKNP V{x:X}"),
+        0xF000 => "LD I, long".into(),
+        o if o & 0xF0FF == 0xF001 => format!("PLANE {x:#X}"),
+        0xF002 => "AUDIO".into(),
+        o if o & 0xF0FF == 0xF007 => format!("LD V{x:X}, DT"),
+        o if o & 0xF0FF == 0xF00A => format!("LD V{x:X}, K"),
+        o if o & 0xF0FF == 0xF015 => format!("LD DT, V{x:X}"),
+        o if o & 0xF0FF == 0xF018 => format!("LD ST, V{x:X}"),
+        o if o & 0xF0FF == 0xF01E => format!("ADD I, V{x:X}"),
+        o if o & 0xF0FF == 0xF029 => format!("LD F, V{x:X}"),
+        o if o & 0xF0FF == 0xF030 => format!("LD HF, V{x:X}"),
+        o if o & 0xF0FF == 0xF033 => format!("LD B, V{x:X}"),
+        o if o & 0xF0FF == 0xF03A => format!("PITCH V{x:X}"),
+        o if o & 0xF0FF == 0xF055 => format!("LD [I], V{x:X}"),
+        o if o & 0xF0FF == 0xF065 => format!("LD V{x:X}, [I]"),
+        o if o & 0xF0FF == 0xF075 => format!("LD R, V{x:X}"),
+        o if o & 0xF0FF == 0xF085 => format!("LD V{x:X}, R"),
+        o => format!("DW {o:#06X}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x00C4), "SCD 0x4");
+        assert_eq!(disassemble(0x1228), "JP 0x228");
+        assert_eq!(disassemble(0x3A0F), "SE VA, 0x0F");
+        assert_eq!(disassemble(0x8AB5), "SUB VA, VB");
+        assert_eq!(disassemble(0xD125), "DRW V1, V2, 0x5");
+        assert_eq!(disassemble(0xF31E), "ADD I, V3");
+        assert_eq!(disassemble(0xE1FF), "DW 0xE1FF");
+    }
+}
@@ -1,63 +1,272 @@
 use std::fs::File;
 use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-use anyhow::Context;
+use anyhow::anyhow;
 use anyhow::Result;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
 
+use crate::cheats::Cheats;
 use crate::chip8;
-use crate::hardware::Hardware;
-use crate::profile::profiles;
+use crate::chip8::HeldKeys;
+use crate::frontend::Frontend;
+use crate::frontend::Input;
+use crate::gdb;
+use crate::gdb::Gdb;
+use crate::headless::Limits;
+use crate::profile;
 use crate::profile::Profile;
+use crate::profiler;
+use crate::profiler::Profiler;
+use crate::recorder::Recorder;
+use crate::screenshot;
+use crate::script;
+use crate::script::Script;
+use crate::timing::Clock;
+use crate::timing::Timing;
+use crate::trace;
+use crate::trace::Tracer;
+use crate::wav::WavCapture;
 use crate::Action;
 use crate::ProcessType;
 use crate::Target;
 
 const TICK: Duration = Duration::from_millis(1_000 / 60);
 
-type Ticker = Box<dyn FnMut(&mut Emulator) -> Option<Action>>;
+type Ticker<F> = fn(&mut Emulator<F>) -> Result<Option<Action>>;
 
-pub(super) struct Emulator {
+/// Files to be written, tools attached and settings for a run.
+#[derive(Debug, Default)]
+pub(super) struct Captures {
+    pub(super) screenshot_on_exit: Option<screenshot::Output>,
+    pub(super) record: Option<screenshot::Output>,
+    pub(super) audio: Option<PathBuf>,
+    pub(super) trace: Option<trace::Options>,
+    pub(super) profile: Option<profiler::Options>,
+    pub(super) script: Option<PathBuf>,
+    pub(super) gdb: Option<SocketAddr>,
+    pub(super) timing: Timing,
+    pub(super) held_keys: HeldKeys,
+    /// Apply the ROM's cheat file. Always on in a window; headless runs only
+    /// apply it with `--cheats`, so that runs being compared or checked aren't
+    /// changed by a stray file.
+    pub(super) cheats: bool,
+}
+
+/// An executed instruction, with the machine state either side of it.
+pub(super) struct Step {
+    pub(super) cycle: u64,
+    pub(super) frame: u32,
+    pub(super) opcode: u16,
+    pub(super) before: chip8::State,
+    pub(super) after: chip8::State,
+}
+
+/// Watches each instruction as it executes, e.g. to trace or profile a run.
+pub(super) trait Observer {
+    /// Called after each instruction. Returning an action ends the run.
+    fn instruction(&mut self, step: &Step) -> Result<Option<Action>>;
+
+    /// Called once the run has ended.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Runs a ROM, showing it through any frontend.
+pub(super) struct Emulator<F> {
     profile: Profile,
-    hardware: Hardware,
+    frontend: F,
     chip8: chip8::Chip8,
+    captures: Captures,
+    recorder: Option<Recorder>,
+    wav: Option<WavCapture>,
+    observers: Vec<Box<dyn Observer>>,
+    cheats: Option<Cheats>,
+    cheat_console: bool,
+    script: Option<Script>,
+    gdb: Option<Gdb>,
+    clock: Clock,
+    limits: Limits,
+    paced: bool,
+    frame: u32,
+    cycle: u64,
 }
 
-impl Emulator {
-    pub(super) fn new(scale: Option<u8>, target: Target) -> Result<Self> {
-        let profile: Profile = *profiles()
-            .get(&target)
-            .context("Unknown target architecture")?;
+impl<F: Frontend> Emulator<F> {
+    pub(super) fn new(target: Target, frontend: F, captures: Captures) -> Result<Self> {
+        let profile = profile::for_target(target)?;
 
-        let hardware = Hardware::new(scale, profile)?;
+        if captures.timing == Timing::Vip && target != Target::Chip8 {
+            return Err(anyhow!("VIP timing is only for the chip8 target"));
+        }
 
-        let chip8 = chip8::Chip8::new(target, profile, Box::new(rand::rng()));
+        let mut chip8 = chip8::Chip8::new(target, profile, Box::new(rand::rng()));
+        chip8.set_held_keys(captures.held_keys);
+        let clock = Clock::new(captures.timing);
 
         Ok(Emulator {
             profile,
-            hardware,
+            frontend,
             chip8,
+            captures,
+            recorder: None,
+            wav: None,
+            observers: Vec::new(),
+            cheats: None,
+            cheat_console: false,
+            script: None,
+            gdb: None,
+            clock,
+            limits: Limits::default(),
+            paced: true,
+            frame: 0,
+            cycle: 0,
         })
     }
 
+    /// Stops the run when any of the limits is hit.
+    pub(super) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Shows every instruction executed to `observer`.
+    pub(super) fn with_observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Seeds the random number generator, so that runs can be repeated.
+    pub(super) fn with_seed(mut self, seed: u64) -> Self {
+        self.chip8.set_rng(Box::new(SmallRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Reads cheat commands from stdin while running.
+    pub(super) fn with_cheat_console(mut self) -> Self {
+        self.cheat_console = true;
+        self
+    }
+
+    /// Runs frames as fast as possible, rather than at 60 Hz.
+    pub(super) fn unthrottled(mut self) -> Self {
+        self.paced = false;
+        self
+    }
+
+    pub(super) fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    pub(super) fn chip8(&self) -> &chip8::Chip8 {
+        &self.chip8
+    }
+
+    pub(super) fn frontend(&self) -> &F {
+        &self.frontend
+    }
+
     pub(super) fn process(&mut self, process_type: ProcessType, filename: &str) -> Result<()> {
-        let rom_data = Self::load_file(filename)?;
+        let rom_data = load_file(filename)?;
 
-        self.chip8.load_rom(&rom_data);
+        self.chip8.load_rom(&rom_data)?;
 
-        let mut ticker: Ticker = match process_type {
-            ProcessType::Run => Box::new(Self::tick_run),
-            // ProcessType::Step => Box::new(Self::tick_step),
+        if self.captures.cheats {
+            let mut cheats = Cheats::load(filename)?;
+            if self.cheat_console {
+                cheats.start_console();
+            }
+            self.cheats = Some(cheats);
+        }
+
+        if let Some(path) = &self.captures.script {
+            self.script = Some(Script::load(path)?);
+        }
+
+        if let Some(address) = self.captures.gdb {
+            self.gdb = Some(Gdb::listen(address)?);
+        }
+
+        if let Some(output) = &self.captures.record {
+            self.recorder = Some(Recorder::new(
+                &output.path,
+                self.profile,
+                output.scale,
+                self.frontend.palette(),
+            )?);
+        }
+
+        if let Some(path) = &self.captures.audio {
+            self.wav = Some(WavCapture::new(path)?);
+        }
+
+        if let Some(options) = &self.captures.trace {
+            self.observers.push(Box::new(Tracer::new(options)?));
+        }
+
+        if let Some(options) = &self.captures.profile {
+            self.observers.push(Box::new(Profiler::new(options)));
+        }
+
+        self.run(process_type, filename)?;
+
+        if let Some(output) = self.captures.screenshot_on_exit.take() {
+            self.save_screenshot(&output.path, output.scale)?;
+        }
+
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish()?;
+        }
+
+        if let Some(wav) = self.wav.take() {
+            wav.finish()?;
+        }
+
+        for observer in std::mem::take(&mut self.observers) {
+            observer.finish()?;
+        }
+
+        if let Some(gdb) = self.gdb.take() {
+            gdb.finish();
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, process_type: ProcessType, filename: &str) -> Result<()> {
+        let ticker: Ticker<F> = match process_type {
+            ProcessType::Run => Self::tick_run,
+            // ProcessType::Step => Self::tick_step,
         };
 
         loop {
+            let start = Instant::now();
+
             // we always want a refresh after a tick, even if about to quit
-            let result_ti = ticker(self);
-            let result_re = self.refresh()?;
+            let result_ti = ticker(self)?;
+            if let Some(cheats) = self.cheats.as_mut() {
+                cheats.apply(&mut self.chip8);
+            }
+            let result_re = self.refresh(filename)?;
+            let result_sc = match self.script.as_mut() {
+                Some(script) => {
+                    let commands = script.frame(&self.chip8, self.frame)?;
+                    self.obey(commands)?
+                }
+                None => None,
+            };
+
+            self.frame += 1;
+            if self.limits.frames.is_some_and(|f| self.frame >= f) {
+                break;
+            }
 
-            match result_ti.or(result_re) {
+            match result_ti.or(result_re).or(result_sc) {
                 Some(Action::Quit) => {
                     // eprintln!("quitting normally");
                     break;
@@ -68,42 +277,134 @@ impl Emulator {
                 }
                 _ => {}
             }
+
+            if self.paced {
+                thread::sleep(TICK.saturating_sub(start.elapsed()));
+            }
         }
 
         Ok(())
     }
 
-    fn load_file(filename: &str) -> Result<Vec<u8>> {
-        let mut rom_data = Vec::new();
-        {
-            let mut f = File::open(filename)?;
-            f.read_to_end(&mut rom_data)?;
+    fn save_screenshot(&self, path: &Path, scale: u8) -> Result<()> {
+        screenshot::Image::render(
+            self.chip8.graphics(),
+            self.profile,
+            self.chip8.resolution_scale(),
+            scale,
+            self.frontend.palette(),
+        )
+        .write_png(path)
+    }
+
+    /// Carries out the commands given by the script's callbacks.
+    fn obey(&mut self, commands: Vec<script::Command>) -> Result<Option<Action>> {
+        let mut action = None;
+        for command in commands {
+            match command {
+                script::Command::SetRegister { x, value } => self.chip8.set_register(x, value),
+                script::Command::Poke { address, value } => self.chip8.poke(address, value),
+                script::Command::Key { key, pressed } => self.chip8.press_key(key, pressed),
+                script::Command::Screenshot { path } => self.save_screenshot(&path, 1)?,
+                script::Command::Stop => action = Some(Action::Quit),
+            }
+        }
+
+        Ok(action)
+    }
+
+    fn toggle_recording(&mut self, filename: &str) -> Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            return recorder.finish();
         }
-        Ok(rom_data)
+
+        // hotkey recordings use the scale given for --record, if any
+        let scale = self.captures.record.as_ref().map_or(1, |r| r.scale);
+        self.recorder = Some(Recorder::new(
+            &screenshot::hotkey_filename(filename, "gif"),
+            self.profile,
+            scale,
+            self.frontend.palette(),
+        )?);
+
+        Ok(())
     }
 
-    fn tick_run(&mut self) -> Option<Action> {
+    fn tick_run(&mut self) -> Result<Option<Action>> {
         let start = Instant::now();
-        let mut remaining = Duration::new(0, 0);
 
-        for _cycles in 0u8..20u8 {
-            self.chip8.emulate_cycle();
+        self.clock.start_frame();
+        while !self.clock.frame_over() {
+            if let Some(gdb) = self.gdb.as_mut() {
+                match gdb.before(&mut self.chip8)? {
+                    gdb::Flow::Run => {}
+                    gdb::Flow::Detach => self.gdb = None,
+                    gdb::Flow::Quit => return Ok(Some(Action::Quit)),
+                }
+            }
 
-            remaining = TICK.saturating_sub(start.elapsed());
+            let pc = self.chip8.program_counter();
+            let before = (!self.observers.is_empty() || self.clock.needs_steps())
+                .then(|| (self.chip8.opcode(), self.chip8.state()));
+            let scripted = self
+                .script
+                .as_ref()
+                .is_some_and(Script::watches_instructions);
+            let written = (scripted || self.gdb.as_ref().is_some_and(Gdb::watches_writes))
+                .then(|| self.chip8.next_writes())
+                .flatten();
 
-            if ((self.profile.lores_display_wait() && !self.chip8.hires_mode())
-                && self.chip8.graphics_needs_refresh())
-                || remaining.is_zero()
-            {
-                break;
+            let mut action = self.chip8.emulate_cycle();
+
+            let step = before.map(|(opcode, before)| Step {
+                cycle: self.cycle,
+                frame: self.frame,
+                opcode,
+                before,
+                after: self.chip8.state(),
+            });
+            if let Some(step) = &step {
+                for observer in &mut self.observers {
+                    action = action.or(observer.instruction(step)?);
+                }
             }
-        }
+            self.clock
+                .tick(step.as_ref(), self.chip8.waiting_for_vblank());
+            self.cycle += 1;
+
+            if let Some(gdb) = self.gdb.as_mut() {
+                gdb.after(written.as_ref());
+            }
+
+            let commands = match self.script.as_mut() {
+                Some(script) if scripted => {
+                    script.instruction(&self.chip8, self.frame, pc, written.as_ref())?
+                }
+                _ => Vec::new(),
+            };
+            action = action.or(self.obey(commands)?);
+
+            if action.is_some() {
+                return Ok(action);
+            }
+
+            if self.limits.hit(
+                pc,
+                self.chip8.program_counter(),
+                self.frame,
+                self.chip8.waiting_for_key(),
+            ) {
+                return Ok(Some(Action::Quit));
+            }
+
+            let out_of_time = self.paced && start.elapsed() >= TICK;
 
-        if !remaining.is_zero() {
-            thread::sleep(remaining);
+            if self.chip8.waiting_for_vblank() || out_of_time {
+                break;
+            }
         }
 
-        None
+        Ok(None)
     }
 
     // fn tick_step(&mut self) -> Option<Action> {
@@ -116,24 +417,177 @@ impl Emulator {
     //     None
     // }
 
-    fn refresh(&mut self) -> Result<Option<Action>> {
-        self.chip8.update_timers();
+    fn refresh(&mut self, filename: &str) -> Result<Option<Action>> {
+        self.chip8.vblank();
+
+        let gfx = self.chip8.graphics();
+        let res_scale = self.chip8.resolution_scale();
+        self.frontend
+            .present(gfx, res_scale, self.chip8.graphics_needs_refresh())?;
+        self.frontend.inspect(&self.chip8)?;
 
-        if self.chip8.graphics_needs_refresh() {
-            self.hardware
-                .refresh_graphics(self.chip8.graphics(), self.chip8.resolution_scale())?;
-            self.chip8.graphics_clear_refresh();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.add_frame(gfx, res_scale, self.frontend.palette())?;
         }
 
-        if self.chip8.audio_sound() {
-            self.hardware.sound_start();
-        } else {
-            self.hardware.sound_stop();
+        self.chip8.graphics_clear_refresh();
+
+        if let Some(wav) = self.wav.as_mut() {
+            wav.add_frame(self.chip8.audio_sound())?;
         }
 
-        self.hardware
-            .event_iter()
-            .find_map(|event| self.chip8.handle_key(&event).transpose())
-            .transpose()
+        self.frontend.set_sounding(self.chip8.audio_sound());
+
+        let mut action = None;
+        for input in self.frontend.poll()? {
+            match input {
+                Input::Key { key, pressed } => self.chip8.press_key(key, pressed),
+                Input::Quit => action = Some(Action::Quit),
+                Input::Screenshot { scale } => {
+                    self.save_screenshot(&screenshot::hotkey_filename(filename, "png"), scale)?;
+                }
+                Input::ToggleRecording => self.toggle_recording(filename)?,
+                Input::Poke { address, value } => self.chip8.poke(address, value),
+            }
+        }
+
+        Ok(action)
+    }
+}
+
+pub(super) fn load_file(filename: &str) -> Result<Vec<u8>> {
+    let mut rom_data = Vec::new();
+    {
+        let mut f = File::open(filename)?;
+        f.read_to_end(&mut rom_data)?;
+    }
+    Ok(rom_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use anyhow::Error;
+    use bitvec::prelude::BitVec;
+
+    use super::Captures;
+    use super::Emulator;
+    use crate::frontend;
+    use crate::frontend::Input;
+    use crate::headless;
+    use crate::headless::Headless;
+    use crate::headless::Limits;
+    use crate::palette;
+    use crate::ProcessType;
+    use crate::Target;
+
+    /// Records what the emulator shows and plays, and replays canned input.
+    #[derive(Default)]
+    struct Fake {
+        presented: Vec<bool>,
+        sounding: Vec<bool>,
+        input: VecDeque<Vec<Input>>,
+    }
+
+    impl frontend::Display for Fake {
+        fn present(&mut self, _gfx: &BitVec, _res_scale: u8, changed: bool) -> Result<(), Error> {
+            self.presented.push(changed);
+            Ok(())
+        }
+
+        fn palette(&self) -> palette::Palette {
+            palette::Preset::Default.palette()
+        }
+    }
+
+    impl frontend::Sound for Fake {
+        fn set_sounding(&mut self, sounding: bool) {
+            self.sounding.push(sounding);
+        }
+    }
+
+    impl frontend::InputSource for Fake {
+        fn poll(&mut self) -> Result<Vec<Input>, Error> {
+            // quit once the script runs out
+            Ok(self.input.pop_front().unwrap_or(vec![Input::Quit]))
+        }
+    }
+
+    fn run(rom: &[u8], input: Vec<Vec<Input>>) -> Result<Fake, Error> {
+        let fake = Fake {
+            input: input.into(),
+            ..Fake::default()
+        };
+        let mut emulator = Emulator::new(Target::Chip8, fake, Captures::default())?.unthrottled();
+        emulator.chip8.load_rom(rom)?;
+        emulator.run(ProcessType::Run, "test.ch8")?;
+        Ok(emulator.frontend)
+    }
+
+    #[test]
+    fn test_every_frame_is_presented_until_quit() -> Result<(), Error> {
+        // when
+        // 00E0: clear the screen, 1202: loop forever
+        let rom = [0x00, 0xE0, 0x12, 0x02];
+
+        // then
+        let fake = run(&rom, vec![vec![], vec![], vec![Input::Quit]])?;
+
+        // verify
+        assert_eq!(fake.presented, vec![true, false, false]);
+        assert_eq!(fake.sounding, vec![false, false, false]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_until_halt_waits_for_scripted_key_presses() -> Result<(), Error> {
+        // when
+        // F00A: wait for a key into V0, 1202: loop forever
+        let rom = [0xF0, 0x0A, 0x12, 0x02];
+        let events = headless::parse_key_press("60:1")?.events();
+        let limits = Limits {
+            until_halt: true,
+            ..Limits::default()
+        }
+        .with_events(&events);
+        let frontend = Headless::new(palette::Preset::Default.palette(), events.to_vec());
+
+        // then
+        let mut emulator = Emulator::new(Target::Chip8, frontend, Captures::default())?
+            .with_limits(limits)
+            .unthrottled();
+        emulator.chip8.load_rom(&rom)?;
+        emulator.run(ProcessType::Run, "test.ch8")?;
+
+        // verify
+        assert_eq!(emulator.chip8.state().registers[0], 1);
+        assert_eq!(emulator.chip8.program_counter(), 0x202);
+        assert!(emulator.frame >= 65);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_input_reaches_the_program() -> Result<(), Error> {
+        // when
+        // F00A: wait for a key into V0, F018: sound for V0 frames, 1204: loop forever
+        let rom = [0xF0, 0x0A, 0xF0, 0x18, 0x12, 0x04];
+        let key = |pressed| vec![Input::Key { key: 3, pressed }];
+
+        // then
+        let fake = run(
+            &rom,
+            vec![vec![], key(true), key(false), vec![], vec![], vec![]],
+        )?;
+
+        // verify
+        // the buzzer sounds while the key is held, the key is only taken on
+        // release, and the sound timer is counted down at the end of the frame
+        // it was set in
+        assert_eq!(
+            fake.sounding,
+            vec![false, false, true, true, true, false, false]
+        );
+        Ok(())
     }
 }
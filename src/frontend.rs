@@ -0,0 +1,61 @@
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+
+use crate::chip8;
+use crate::palette;
+
+/// Input from the user, whichever frontend it came from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Input {
+    /// A CHIP-8 key (0x0-0xF) going down or up
+    Key {
+        key: u8,
+        pressed: bool,
+    },
+    Quit,
+    /// Save a screenshot, at this many pixels per display pixel
+    Screenshot {
+        scale: u8,
+    },
+    /// Start or stop a hotkey recording
+    ToggleRecording,
+    /// Write a byte into emulated memory, from the memory editor
+    Poke {
+        address: u16,
+        value: u8,
+    },
+}
+
+/// Shows the emulated display.
+pub(crate) trait Display {
+    /// Called once per 60 Hz frame; `changed` is false if the display is the
+    /// same as at the previous call.
+    fn present(&mut self, gfx: &BitVec, res_scale: u8, changed: bool) -> Result<()>;
+
+    /// The palette currently shown, for screenshots and recordings.
+    fn palette(&self) -> palette::Palette;
+
+    /// Shows the machine state, for frontends with a debug view. Called once
+    /// per 60 Hz frame, after `present`.
+    fn inspect(&mut self, _chip8: &chip8::Chip8) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays the buzzer.
+pub(crate) trait Sound {
+    /// Called once per 60 Hz frame with whether the sound timer is running.
+    fn set_sounding(&mut self, sounding: bool);
+}
+
+/// Supplies user input.
+pub(crate) trait InputSource {
+    /// Returns the input received since the last call, without blocking.
+    /// Called once per 60 Hz frame.
+    fn poll(&mut self) -> Result<Vec<Input>>;
+}
+
+/// Everything the emulator needs to run a ROM.
+pub(crate) trait Frontend: Display + Sound + InputSource {}
+
+impl<T: Display + Sound + InputSource> Frontend for T {}
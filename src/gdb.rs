@@ -0,0 +1,612 @@
+//! A GDB remote serial protocol stub, so that a gdb-compatible debugger can
+//! attach over TCP with `target remote`. The machine stops before its first
+//! instruction until the debugger continues it, and again on a breakpoint, a
+//! watched write, a single step or an interrupt. The registers are described
+//! to the debugger by `TARGET_XML`.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::ops::RangeInclusive;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::chip8;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chipper.chip8">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// V0-VF, I, PC, SP, DT and ST, 16-bit registers little-endian
+const REGISTER_BYTES: usize = 23;
+
+// the interrupt sent by the debugger while the machine runs
+const INTERRUPT: u8 = 0x03;
+
+fn to_bytes(state: &chip8::State) -> [u8; REGISTER_BYTES] {
+    let mut bytes = [0; REGISTER_BYTES];
+    bytes[..16].copy_from_slice(&state.registers);
+    bytes[16..18].copy_from_slice(&state.i.to_le_bytes());
+    bytes[18..20].copy_from_slice(&state.pc.to_le_bytes());
+    bytes[20] = state.sp;
+    bytes[21] = state.delay_timer;
+    bytes[22] = state.sound_timer;
+    bytes
+}
+
+fn from_bytes(bytes: &[u8; REGISTER_BYTES]) -> chip8::State {
+    let mut registers = [0; 16];
+    registers.copy_from_slice(&bytes[..16]);
+
+    chip8::State {
+        registers,
+        i: u16::from_le_bytes([bytes[16], bytes[17]]),
+        pc: u16::from_le_bytes([bytes[18], bytes[19]]),
+        sp: bytes[20],
+        delay_timer: bytes[21],
+        sound_timer: bytes[22],
+    }
+}
+
+/// Where register `n`, numbered as in `TARGET_XML`, sits in the register bytes.
+fn register_span(n: usize) -> Option<std::ops::Range<usize>> {
+    match n {
+        0..=15 => Some(n..n + 1),
+        16 => Some(16..18),
+        17 => Some(18..20),
+        18..=20 => Some(n + 2..n + 3),
+        _ => None,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// Frames a reply as a packet: `$data#checksum`.
+fn packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, u8::wrapping_add);
+    format!("${data}#{checksum:02x}")
+}
+
+/// Why the machine stopped, as reported to the debugger.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Stop {
+    Signal,
+    Breakpoint,
+    Watch(u16),
+}
+
+impl Stop {
+    fn reply(self) -> String {
+        match self {
+            Self::Signal => "S05".into(),
+            Self::Breakpoint => "T05swbreak:;".into(),
+            Self::Watch(address) => format!("T05watch:{address:x};"),
+        }
+    }
+}
+
+/// What to do after a packet from the debugger.
+#[derive(Debug, Eq, PartialEq)]
+enum Reply {
+    Packet(String),
+    Resume,
+    Step,
+    Detach,
+    Kill,
+}
+
+impl From<&str> for Reply {
+    fn from(data: &str) -> Self {
+        Self::Packet(data.into())
+    }
+}
+
+/// The breakpoints and watchpoints, and the packets that read and change the
+/// machine while it's stopped.
+#[derive(Debug, Default)]
+struct Session {
+    breakpoints: HashSet<u16>,
+    watchpoints: Vec<RangeInclusive<u16>>,
+}
+
+impl Session {
+    fn handle(&mut self, packet: &str, chip8: &mut chip8::Chip8) -> Reply {
+        self.command(packet, chip8).unwrap_or_else(|| "E01".into())
+    }
+
+    /// Handles a packet, or returns None if it's malformed. Packets that aren't
+    /// supported get an empty reply.
+    fn command(&mut self, packet: &str, chip8: &mut chip8::Chip8) -> Option<Reply> {
+        let (kind, rest) = packet.split_at(packet.len().min(1));
+
+        let reply = match kind {
+            "?" => Reply::Packet(Stop::Signal.reply()),
+            "g" => Reply::Packet(to_hex(&to_bytes(&chip8.state()))),
+            "G" => {
+                let bytes = from_hex(rest)?;
+                chip8.set_state(from_bytes(&bytes.try_into().ok()?));
+                "OK".into()
+            }
+            "p" => {
+                let span = register_span(usize::from_str_radix(rest, 16).ok()?)?;
+                Reply::Packet(to_hex(&to_bytes(&chip8.state())[span]))
+            }
+            "P" => {
+                let (n, value) = rest.split_once('=')?;
+                let span = register_span(usize::from_str_radix(n, 16).ok()?)?;
+                let value = from_hex(value)?;
+                let mut bytes = to_bytes(&chip8.state());
+                bytes
+                    .get_mut(span)
+                    .filter(|b| b.len() == value.len())?
+                    .copy_from_slice(&value);
+                chip8.set_state(from_bytes(&bytes));
+                "OK".into()
+            }
+            "m" => {
+                let (address, length) = rest.split_once(',')?;
+                let start = usize::from(parse_hex(address)?);
+                let end = start.saturating_add(usize::from_str_radix(length, 16).ok()?);
+                let memory = chip8.memory().get(start..end.min(chip8.memory().len()))?;
+                if memory.is_empty() {
+                    return None;
+                }
+                Reply::Packet(to_hex(memory))
+            }
+            "M" => {
+                let (address, data) = rest.split_once(':')?;
+                let (address, _) = address.split_once(',')?;
+                let address = parse_hex(address)?;
+                let data = from_hex(data)?;
+                if usize::from(address) + data.len() > chip8.memory().len() {
+                    return None;
+                }
+                // counted in usize, as a write can end at 0xFFFF
+                for (at, value) in (usize::from(address)..).zip(data) {
+                    chip8.poke(u16::try_from(at).ok()?, value);
+                }
+                "OK".into()
+            }
+            "c" | "s" => {
+                if !rest.is_empty() {
+                    let mut state = chip8.state();
+                    state.pc = parse_hex(rest)?;
+                    chip8.set_state(state);
+                }
+                if kind == "c" {
+                    Reply::Resume
+                } else {
+                    Reply::Step
+                }
+            }
+            "Z" | "z" => self.point(kind == "Z", rest)?,
+            "D" => Reply::Detach,
+            "k" => Reply::Kill,
+            "H" => "OK".into(),
+            "q" => Self::query(rest),
+            "v" if rest == "Kill" || rest.starts_with("Kill;") => Reply::Kill,
+            _ => "".into(),
+        };
+
+        Some(reply)
+    }
+
+    /// Adds or removes a breakpoint or write watchpoint: `type,address,kind`.
+    fn point(&mut self, insert: bool, packet: &str) -> Option<Reply> {
+        let mut fields = packet.split(',');
+        let (kind, address, length) = (fields.next()?, fields.next()?, fields.next()?);
+        let address = parse_hex(address)?;
+
+        match kind {
+            // software and hardware breakpoints behave the same here
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+            }
+            "2" => {
+                let length = parse_hex(length)?.max(1);
+                let watch = address..=address.saturating_add(length - 1);
+                self.watchpoints.retain(|w| *w != watch);
+                if insert {
+                    self.watchpoints.push(watch);
+                }
+            }
+            // read and access watchpoints aren't supported
+            _ => return Some("".into()),
+        }
+
+        Some("OK".into())
+    }
+
+    fn query(query: &str) -> Reply {
+        if query.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+".into();
+        }
+
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            return Self::target_xml(range).unwrap_or_else(|| "E01".into());
+        }
+
+        match query {
+            "Attached" => "1".into(),
+            "C" => "QC1".into(),
+            "fThreadInfo" => "m1".into(),
+            "sThreadInfo" => "l".into(),
+            _ => "".into(),
+        }
+    }
+
+    /// Reads part of the target description: `offset,length`.
+    fn target_xml(range: &str) -> Option<Reply> {
+        let (offset, length) = range.split_once(',')?;
+        let start = usize::from_str_radix(offset, 16)
+            .ok()?
+            .min(TARGET_XML.len());
+        let end = start
+            .saturating_add(usize::from_str_radix(length, 16).ok()?)
+            .min(TARGET_XML.len());
+
+        // 'l' marks the last part
+        let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+        Some(Reply::Packet(format!(
+            "{more}{}",
+            TARGET_XML.get(start..end)?
+        )))
+    }
+
+    /// The first watchpoint that a write to `written` hits, if any.
+    fn watched(&self, written: &RangeInclusive<u16>) -> Option<u16> {
+        self.watchpoints
+            .iter()
+            .find(|w| w.start() <= written.end() && written.start() <= w.end())
+            .map(|w| *w.start().max(written.start()))
+    }
+}
+
+/// What the emulator should do after the debugger has had its say.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Flow {
+    Run,
+    Detach,
+    Quit,
+}
+
+/// A debugger attached over TCP.
+#[must_use]
+pub(super) struct Gdb {
+    stream: TcpStream,
+    session: Session,
+    started: bool,
+    stepping: bool,
+    stop: Option<Stop>,
+}
+
+impl Gdb {
+    /// Waits for a debugger to connect to `address`.
+    pub(super) fn listen(address: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Unable to listen for gdb on {address}"))?;
+        eprintln!("waiting for gdb on {address}");
+
+        let (stream, peer) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        eprintln!("gdb attached from {peer}");
+
+        Ok(Self {
+            stream,
+            session: Session::default(),
+            started: false,
+            stepping: false,
+            stop: None,
+        })
+    }
+
+    /// Called before each instruction, to stop for the debugger if needed.
+    pub(super) fn before(&mut self, chip8: &mut chip8::Chip8) -> Result<Flow> {
+        if !self.started {
+            self.started = true;
+            return self.serve(chip8);
+        }
+
+        if self.stop.is_none() && self.session.breakpoints.contains(&chip8.program_counter()) {
+            self.stop = Some(Stop::Breakpoint);
+        }
+
+        if self.stop.is_none() {
+            let mut byte = [0];
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Ok(Flow::Detach),
+                Ok(_) if byte[0] == INTERRUPT => self.stop = Some(Stop::Signal),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let Some(stop) = self.stop.take() else {
+            return Ok(Flow::Run);
+        };
+        self.stream.set_nonblocking(false)?;
+        self.send(&stop.reply())?;
+        self.serve(chip8)
+    }
+
+    /// Called after each instruction, with the memory it stored to.
+    pub(super) fn after(&mut self, written: Option<&RangeInclusive<u16>>) {
+        if self.stepping {
+            self.stepping = false;
+            self.stop = Some(Stop::Signal);
+        }
+
+        if let Some(address) = written.and_then(|w| self.session.watched(w)) {
+            self.stop = Some(Stop::Watch(address));
+        }
+    }
+
+    /// True if the debugger needs to know what each instruction stores to.
+    pub(super) fn watches_writes(&self) -> bool {
+        !self.session.watchpoints.is_empty()
+    }
+
+    /// Tells the debugger the program has ended.
+    pub(super) fn finish(mut self) {
+        // the debugger may already have gone
+        let _ = self.send("W00");
+    }
+
+    /// Answers the debugger until it resumes the machine.
+    fn serve(&mut self, chip8: &mut chip8::Chip8) -> Result<Flow> {
+        loop {
+            let Some(packet) = self.receive()? else {
+                return Ok(Flow::Detach);
+            };
+
+            match self.session.handle(&packet, chip8) {
+                Reply::Packet(data) => self.send(&data)?,
+                reply @ (Reply::Resume | Reply::Step) => {
+                    self.stepping = reply == Reply::Step;
+                    self.stream.set_nonblocking(true)?;
+                    return Ok(Flow::Run);
+                }
+                Reply::Detach => {
+                    self.send("OK")?;
+                    return Ok(Flow::Detach);
+                }
+                Reply::Kill => return Ok(Flow::Quit),
+            }
+        }
+    }
+
+    /// Reads the next packet, acknowledging it, or None if the debugger has
+    /// gone.
+    fn receive(&mut self) -> Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+            if expected == Some(data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        self.stream.write_all(packet(data).as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use anyhow::Error;
+
+    use super::packet;
+    use super::Reply;
+    use super::Session;
+    use super::TARGET_XML;
+    use crate::chip8::Chip8;
+    use crate::profile;
+    use crate::Target;
+
+    fn chip8() -> Result<Chip8, Error> {
+        let mut chip8 = Chip8::new(
+            Target::Chip8,
+            *profile::profiles()
+                .get(&Target::Chip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        // 6A42: VA = 42, A321: I = 321
+        chip8.load_rom(&[0x6A, 0x42, 0xA3, 0x21])?;
+        Ok(chip8)
+    }
+
+    #[test]
+    fn test_packet() {
+        assert_eq!(packet("OK"), "$OK#9a");
+        assert_eq!(packet(""), "$#00");
+    }
+
+    #[test]
+    fn test_registers_and_memory() -> Result<(), Error> {
+        // when
+        let mut chip8 = chip8()?;
+        let mut session = Session::default();
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+
+        // then
+        let registers = session.handle("g", &mut chip8);
+        let pc = session.handle("p11", &mut chip8);
+        let set_pc = session.handle("P11=0002", &mut chip8);
+        let set_v0 = session.handle("P0=7f", &mut chip8);
+        let write = session.handle("M300,2:beef", &mut chip8);
+        let read = session.handle("m2ff,4", &mut chip8);
+        let bad = session.handle("m10000,1", &mut chip8);
+
+        // verify
+        assert_eq!(
+            registers,
+            Reply::Packet(format!(
+                "{}42{}21030402000000",
+                "00".repeat(10),
+                "00".repeat(5)
+            ))
+        );
+        assert_eq!(pc, "0402".into());
+        assert_eq!(set_pc, "OK".into());
+        assert_eq!(set_v0, "OK".into());
+        assert_eq!(write, "OK".into());
+        assert_eq!(read, "00beef00".into());
+        assert_eq!(bad, "E01".into());
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert_eq!(chip8.state().registers[0], 0x7F);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_at_the_end_of_64k() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::XoChip,
+            *profile::profiles()
+                .get(&Target::XoChip)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        let mut session = Session::default();
+
+        // then
+        let write = session.handle("Mfffe,2:beef", &mut chip8);
+        let past = session.handle("Mffff,2:aabb", &mut chip8);
+        let read = session.handle("mfffe,2", &mut chip8);
+
+        // verify
+        assert_eq!(write, "OK".into());
+        assert_eq!(past, "E01".into());
+        assert_eq!(read, "beef".into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_breakpoints_watchpoints_and_target_description() -> Result<(), Error> {
+        // when
+        let mut chip8 = chip8()?;
+        let mut session = Session::default();
+
+        // then
+        let replies = [
+            session.handle("Z0,202,2", &mut chip8),
+            session.handle("Z2,300,4", &mut chip8),
+            session.handle("Z2,310,1", &mut chip8),
+            session.handle("z2,310,1", &mut chip8),
+            session.handle("Z3,300,1", &mut chip8),
+            session.handle("c", &mut chip8),
+            session.handle("s200", &mut chip8),
+        ];
+        let first = session.handle("qXfer:features:read:target.xml:0,10", &mut chip8);
+        let last = session.handle("qXfer:features:read:target.xml:10,1000", &mut chip8);
+
+        // verify
+        assert_eq!(
+            replies,
+            [
+                "OK".into(),
+                "OK".into(),
+                "OK".into(),
+                "OK".into(),
+                "".into(),
+                Reply::Resume,
+                Reply::Step
+            ]
+        );
+        assert!(session.breakpoints.contains(&0x202));
+        assert_eq!(session.watched(&(0x2FE..=0x301)), Some(0x300));
+        assert_eq!(session.watched(&(0x310..=0x310)), None);
+        assert_eq!(first, Reply::Packet(format!("m{}", &TARGET_XML[..0x10])));
+        assert_eq!(last, Reply::Packet(format!("l{}", &TARGET_XML[0x10..])));
+        Ok(())
+    }
+}
@@ -0,0 +1,515 @@
+//! A reinforcement-learning environment in the style of Gym: `reset` starts
+//! the ROM again from a seed, and `step` holds some keys down for a number of
+//! frames and returns what the display shows, with a reward. Runs are
+//! headless and deterministic, and the machine can be copied and put back,
+//! e.g. for a tree search. `chipper gym` drives an environment with commands
+//! on stdin, for use from other languages.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::value_parser;
+use clap::Args;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use crate::chip8::Chip8;
+use crate::emulator;
+use crate::headless;
+use crate::profile;
+use crate::profile::Profile;
+use crate::screenshot;
+use crate::timing;
+use crate::Target;
+
+const HELP: &str = "\
+reset [SEED]          start again, replying with the first observation
+step [KEYS] [FRAMES]  hold KEYS (hex digits, or - for none) down for FRAMES
+save NAME | load NAME copy the machine, or put a copy back";
+
+/// How an environment runs and what it observes.
+#[derive(Args, Debug, Clone)]
+pub(super) struct Options {
+    /// ROM filename to load
+    #[clap(short, long)]
+    file: String,
+
+    /// Target architecture to emulate
+    #[clap(short, long, value_enum, default_value_t = Target::Chip8)]
+    target: Target,
+
+    /// Frames run by each step that doesn't say how many
+    #[clap(long, default_value_t = 4)]
+    frame_skip: u32,
+
+    /// Average observations over blocks of this many pixels square
+    #[clap(long, value_parser = value_parser!(u8).range(1..=16), default_value_t = 1)]
+    downsample: u8,
+
+    /// Reward each step with the increase in the byte at this address
+    #[clap(long, value_parser = headless::parse_address)]
+    reward: Option<u16>,
+
+    /// How long instructions take: the same for each, or as on the COSMAC VIP
+    /// (chip8 only)
+    #[clap(long, value_enum, default_value_t = timing::Timing::Fixed)]
+    timing: timing::Timing,
+}
+
+/// Scores a step from the memory before and after it.
+pub(super) type Reward = Box<dyn Fn(&[u8], &[u8]) -> f64>;
+
+/// Rewards the increase in the byte at `address`, e.g. a score.
+pub(super) fn reward_address(address: u16) -> Reward {
+    let at = usize::from(address);
+    Box::new(move |before, after| {
+        let byte = |memory: &[u8]| f64::from(memory.get(at).copied().unwrap_or_default());
+        byte(after) - byte(before)
+    })
+}
+
+/// What happened during a step.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct Info {
+    pub(super) frame: u32,
+    pub(super) reward: f64,
+    /// True once the program has exited or hit an invalid state.
+    pub(super) done: bool,
+}
+
+/// A copy of the machine, to go back to later.
+#[derive(Clone)]
+pub(super) struct Snapshot {
+    chip8: Chip8,
+    clock: timing::Clock,
+    frame: u32,
+    done: bool,
+}
+
+/// A ROM run one step at a time.
+#[must_use]
+pub(super) struct Env {
+    options: Options,
+    profile: Profile,
+    rom: Vec<u8>,
+    reward: Option<Reward>,
+    state: Snapshot,
+}
+
+impl Env {
+    pub(super) fn new(options: Options, rom: Vec<u8>) -> Result<Self> {
+        let profile = profile::for_target(options.target)?;
+        if options.timing == timing::Timing::Vip && options.target != Target::Chip8 {
+            return Err(anyhow!("VIP timing is only for the chip8 target"));
+        }
+        let state = Self::start(&options, profile, &rom, 0)?;
+
+        Ok(Self {
+            options,
+            profile,
+            rom,
+            reward: None,
+            state,
+        })
+    }
+
+    pub(super) fn with_reward(mut self, reward: Reward) -> Self {
+        self.reward = Some(reward);
+        self
+    }
+
+    fn start(options: &Options, profile: Profile, rom: &[u8], seed: u64) -> Result<Snapshot> {
+        let mut chip8 = Chip8::new(
+            options.target,
+            profile,
+            Box::new(SmallRng::seed_from_u64(seed)),
+        );
+        chip8.load_rom(rom)?;
+
+        Ok(Snapshot {
+            chip8,
+            clock: timing::Clock::new(options.timing),
+            frame: 0,
+            done: false,
+        })
+    }
+
+    /// Starts the ROM again, with RND seeded by `seed`.
+    pub(super) fn reset(&mut self, seed: u64) -> Result<Vec<u8>> {
+        self.state = Self::start(&self.options, self.profile, &self.rom, seed)?;
+        Ok(self.observation())
+    }
+
+    /// Runs `frames` frames, or the frame skip if not given, with `keys` held
+    /// down and every other key up.
+    pub(super) fn step(&mut self, keys: &[u8], frames: Option<u32>) -> (Vec<u8>, Info) {
+        let before = self
+            .reward
+            .is_some()
+            .then(|| self.state.chip8.memory().to_vec());
+
+        for key in 0..16 {
+            self.state.chip8.press_key(key, keys.contains(&key));
+        }
+        for _ in 0..frames.unwrap_or(self.options.frame_skip) {
+            if self.state.done {
+                break;
+            }
+            self.frame();
+        }
+
+        let reward = match (&self.reward, before) {
+            (Some(reward), Some(before)) => reward(&before, self.state.chip8.memory()),
+            _ => 0.0,
+        };
+        let info = Info {
+            frame: self.state.frame,
+            reward,
+            done: self.state.done,
+        };
+
+        (self.observation(), info)
+    }
+
+    /// Runs one frame, as the emulator does.
+    fn frame(&mut self) {
+        let Snapshot {
+            chip8,
+            clock,
+            frame,
+            done,
+        } = &mut self.state;
+
+        clock.start_frame();
+        while !clock.frame_over() {
+            let before = clock.needs_steps().then(|| (chip8.opcode(), chip8.state()));
+            if chip8.emulate_cycle().is_some() {
+                *done = true;
+                break;
+            }
+
+            // the clock only looks at the instruction, not the cycle count
+            let step = before.map(|(opcode, before)| emulator::Step {
+                cycle: 0,
+                frame: *frame,
+                opcode,
+                before,
+                after: chip8.state(),
+            });
+            clock.tick(step.as_ref(), chip8.waiting_for_vblank());
+            if chip8.waiting_for_vblank() {
+                break;
+            }
+        }
+
+        chip8.vblank();
+        chip8.graphics_clear_refresh();
+        *frame += 1;
+    }
+
+    /// The width and height of an observation.
+    pub(super) fn shape(&self) -> (usize, usize) {
+        let downsample = usize::from(self.options.downsample);
+        (
+            usize::from(self.profile.screen_width()).div_ceil(downsample),
+            usize::from(self.profile.screen_height()).div_ceil(downsample),
+        )
+    }
+
+    /// The display, a row at a time, with each value the share of lit pixels
+    /// in a block from 0 to 255.
+    pub(super) fn observation(&self) -> Vec<u8> {
+        let chip8 = &self.state.chip8;
+        let pixels =
+            screenshot::indexed(chip8.graphics(), self.profile, chip8.resolution_scale(), 1);
+        let width = usize::from(self.profile.screen_width());
+        let height = usize::from(self.profile.screen_height());
+        let downsample = usize::from(self.options.downsample);
+        let (columns, rows) = self.shape();
+
+        let mut observation = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let ys = row * downsample..((row + 1) * downsample).min(height);
+                let xs = column * downsample..((column + 1) * downsample).min(width);
+                let count = ys.len() * xs.len();
+                let lit: usize = ys
+                    .flat_map(|y| xs.clone().map(move |x| y * width + x))
+                    .map(|at| usize::from(pixels[at] != 0))
+                    .sum();
+                observation.push(u8::try_from(lit * 255 / count).unwrap_or(u8::MAX));
+            }
+        }
+
+        observation
+    }
+
+    pub(super) fn snapshot(&self) -> Snapshot {
+        self.state.clone()
+    }
+
+    pub(super) fn restore(&mut self, snapshot: &Snapshot) {
+        self.state = snapshot.clone();
+    }
+}
+
+fn parse_keys(value: &str) -> Result<Vec<u8>> {
+    if value == "-" {
+        return Ok(Vec::new());
+    }
+
+    value
+        .chars()
+        .map(|c| {
+            c.to_digit(16)
+                .and_then(|k| u8::try_from(k).ok())
+                .ok_or_else(|| anyhow!("invalid key '{c}'"))
+        })
+        .collect()
+}
+
+/// Formats an observation and what happened as a reply line.
+fn reply(env: &Env, observation: &[u8], info: &Info) -> String {
+    let (width, height) = env.shape();
+    let mut line = format!(
+        "frame={} reward={} done={} width={width} height={height} obs=",
+        info.frame,
+        info.reward,
+        u8::from(info.done)
+    );
+    for value in observation {
+        let _ = write!(line, "{value:02x}");
+    }
+    line
+}
+
+fn reset(env: &mut Env, seed: u64) -> Result<String> {
+    let observation = env.reset(seed)?;
+    let info = Info {
+        frame: 0,
+        reward: 0.0,
+        done: false,
+    };
+    Ok(reply(env, &observation, &info))
+}
+
+fn step(env: &mut Env, keys: &str, frames: Option<&str>) -> Result<String> {
+    let keys = parse_keys(keys)?;
+    let frames = frames
+        .map(|f| f.parse().context("invalid frame count"))
+        .transpose()?;
+
+    let (observation, info) = env.step(&keys, frames);
+    Ok(reply(env, &observation, &info))
+}
+
+/// Serves commands read from `input`, one reply line each, until it ends.
+fn serve(env: &mut Env, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut snapshots = HashMap::new();
+
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let result = match words[..] {
+            [] => continue,
+            ["help"] => Ok(HELP.to_string()),
+            ["reset"] => reset(env, 0),
+            ["reset", seed] => seed
+                .parse()
+                .context("invalid seed")
+                .and_then(|seed| reset(env, seed)),
+            ["step"] => step(env, "-", None),
+            ["step", keys] => step(env, keys, None),
+            ["step", keys, frames] => step(env, keys, Some(frames)),
+            ["save", name] => {
+                snapshots.insert(name.to_string(), env.snapshot());
+                Ok("ok".to_string())
+            }
+            ["load", name] => snapshots
+                .get(name)
+                .map(|snapshot| {
+                    env.restore(snapshot);
+                    "ok".to_string()
+                })
+                .ok_or_else(|| anyhow!("no snapshot '{name}'")),
+            _ => Err(anyhow!("unknown command '{line}', try 'help'")),
+        };
+
+        match result {
+            Ok(text) => writeln!(output, "{text}")?,
+            Err(e) => writeln!(output, "error: {e:#}")?,
+        }
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Runs the ROM as an environment driven from stdin.
+pub(super) fn run(options: Options) -> Result<()> {
+    let rom = emulator::load_file(&options.file)
+        .with_context(|| format!("Unable to read ROM {}", options.file))?;
+
+    let reward = options.reward;
+    let mut env = Env::new(options, rom)?;
+    if let Some(address) = reward {
+        env = env.with_reward(reward_address(address));
+    }
+
+    serve(&mut env, io::stdin().lock(), io::stdout().lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use super::reward_address;
+    use super::serve;
+    use super::Env;
+    use super::Options;
+    use crate::timing::Timing;
+    use crate::Target;
+
+    fn options(downsample: u8) -> Options {
+        Options {
+            file: String::new(),
+            target: Target::Chip8,
+            frame_skip: 4,
+            downsample,
+            reward: None,
+            timing: Timing::Fixed,
+        }
+    }
+
+    #[test]
+    fn test_runs_repeat_from_a_seed_or_snapshot() -> Result<(), Error> {
+        // when
+        // C00F: V0 = random digit, F029: I = its font sprite, 00E0: clear,
+        // D015: draw it, 1202: go again
+        let rom = vec![0xC0, 0x0F, 0xF0, 0x29, 0x00, 0xE0, 0xD0, 0x15, 0x12, 0x00];
+        let mut env = Env::new(options(1), rom)?;
+
+        // then
+        env.reset(7)?;
+        let snapshot = env.snapshot();
+        let (first, info) = env.step(&[], Some(3));
+        env.restore(&snapshot);
+        let (restored, _) = env.step(&[], Some(3));
+        env.reset(7)?;
+        let (reset, _) = env.step(&[], Some(3));
+
+        // verify
+        assert_eq!(info.frame, 3);
+        assert!(!info.done);
+        assert_eq!(first, restored);
+        assert_eq!(first, reset);
+        assert_eq!(first.len(), 64 * 32);
+        Ok(())
+    }
+
+    #[test]
+    fn test_vip_timing() -> Result<(), Error> {
+        // when
+        // 7001: V0 += 1, 1200: go again
+        let rom = vec![0x70, 0x01, 0x12, 0x00];
+        let vip = Options {
+            timing: Timing::Vip,
+            ..options(1)
+        };
+        let mut fixed = Env::new(options(1), rom.clone())?;
+        let mut original = Env::new(vip.clone(), rom.clone())?;
+
+        // then
+        fixed.step(&[], Some(1));
+        original.step(&[], Some(1));
+
+        // verify
+        // 20 instructions, against 50 and 52 cycles each out of 2598
+        assert_eq!(fixed.state.chip8.state().registers[0], 10);
+        assert_eq!(original.state.chip8.state().registers[0], 26);
+        assert!(Env::new(
+            Options {
+                target: Target::SuperChip,
+                ..vip
+            },
+            rom
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_and_reward() -> Result<(), Error> {
+        // when
+        // 6105: V1 = 5, E1A1: skip unless key 5 is down, 7001: V0 += 1,
+        // A300: I = 300, F055: store V0 at 300, 1202: loop
+        let rom = vec![
+            0x61, 0x05, 0xE1, 0xA1, 0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x02,
+        ];
+        let mut env = Env::new(options(1), rom)?.with_reward(reward_address(0x300));
+
+        // then
+        let (_, pressed) = env.step(&[5], Some(2));
+        let (_, released) = env.step(&[], None);
+
+        // verify
+        assert!(pressed.reward >= 1.0);
+        assert!(released.reward.abs() < f64::EPSILON);
+        assert_eq!(released.frame, 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsampled_observation() -> Result<(), Error> {
+        // when
+        // A000: I = the sprite for 0, D005: draw it at 0,0
+        let mut env = Env::new(options(8), vec![0xA0, 0x00, 0xD0, 0x05, 0x12, 0x04])?;
+
+        // then
+        // the first frame ends waiting for the display, as the emulator's does
+        let (observation, _) = env.step(&[], Some(2));
+
+        // verify
+        assert_eq!(env.shape(), (8, 4));
+        // 14 of the block's 64 pixels are lit
+        assert_eq!(observation[0], 55);
+        assert!(observation[1..].iter().all(|v| *v == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        assert!(Env::new(options(1), vec![0; 3584]).is_ok());
+        assert!(Env::new(options(1), vec![0; 3585]).is_err());
+    }
+
+    #[test]
+    fn test_serve() -> Result<(), Error> {
+        // when
+        let mut env = Env::new(options(8), vec![0x12, 0x00])?;
+        let input = "reset 3\nstep 5 2\nsave a\nload a\nload b\nstep x\n";
+
+        // then
+        let mut output = Vec::new();
+        serve(&mut env, input.as_bytes(), &mut output)?;
+
+        // verify
+        let blank = "0".repeat(64);
+        assert_eq!(
+            String::from_utf8(output)?,
+            format!(
+                "frame=0 reward=0 done=0 width=8 height=4 obs={blank}\n\
+                 frame=2 reward=0 done=0 width=8 height=4 obs={blank}\n\
+                 ok\nok\nerror: no snapshot 'b'\nerror: invalid key 'x'\n"
+            )
+        );
+        Ok(())
+    }
+}
@@ -2,72 +2,187 @@ use anyhow::anyhow;
 use anyhow::Error;
 use anyhow::Result;
 use bitvec::prelude::BitVec;
-use sdl2::event::EventPollIterator;
-use sdl2::pixels::Color;
+use sdl2::event::Event;
+use sdl2::event::WindowEvent;
+use sdl2::hint;
+use sdl2::keyboard::Mod;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
+use sdl2::render::Texture;
+use sdl2::video::FullscreenType;
 use sdl2::video::Window;
+use sdl2::video::WindowPos;
 use sdl2::EventPump;
 use sdl2::VideoSubsystem;
 
 use crate::audio;
+use crate::chip8;
+use crate::controller;
+use crate::frontend;
+use crate::frontend::Input;
+use crate::memview::MemoryViewer;
+use crate::overlay::Overlay;
+use crate::palette;
 use crate::profile;
 
-const WHITE: Color = Color::RGB(240, 240, 240);
-const BLACK: Color = Color::RGB(15, 15, 15);
+// the keyboard keys laid out like the COSMAC VIP's hex keypad
+const KEYPAD: [(Scancode, u8); 16] = [
+    (Scancode::Num1, 0x1),
+    (Scancode::Num2, 0x2),
+    (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0xc),
+    (Scancode::Q, 0x4),
+    (Scancode::W, 0x5),
+    (Scancode::E, 0x6),
+    (Scancode::R, 0xd),
+    (Scancode::A, 0x7),
+    (Scancode::S, 0x8),
+    (Scancode::D, 0x9),
+    (Scancode::F, 0xe),
+    (Scancode::Z, 0xa),
+    (Scancode::X, 0x0),
+    (Scancode::C, 0xb),
+    (Scancode::V, 0xf),
+];
+
+/// The keyboard scancode that produces the given CHIP-8 key
+pub(super) fn key_scancode(key_num: u8) -> Option<Scancode> {
+    KEYPAD
+        .iter()
+        .find_map(|(code, num)| (*num == key_num).then_some(*code))
+}
+
+/// The CHIP-8 key produced by the given keyboard scancode
+pub(super) fn scancode_key(code: Scancode) -> Option<u8> {
+    KEYPAD
+        .iter()
+        .find_map(|(c, num)| (*c == code).then_some(*num))
+}
 
 #[must_use]
 pub(super) struct Hardware {
     profile: profile::Profile,
-    scale: u8,
     canvas: Canvas<Window>,
+    texture: Texture,
     audio: audio::Audio,
+    controllers: controller::Controllers,
+    palette: palette::Selector,
+    redraw: bool,
     events: EventPump,
+    overlay: Option<Overlay>,
+    memory_viewer: Option<MemoryViewer>,
 }
 
 impl Hardware {
-    pub(super) fn new(scale: Option<u8>, profile: profile::Profile) -> Result<Self> {
+    pub(super) fn new(
+        scale: Option<u8>,
+        display: Option<u8>,
+        profile: profile::Profile,
+        mapping: controller::Mapping,
+        palette: palette::Palette,
+        debug_overlay: bool,
+        memory_viewer: bool,
+    ) -> Result<Self> {
         let sdl_context = sdl2::init().map_err(Error::msg)?;
 
         let video = sdl_context.video().map_err(Error::msg)?;
-        let scale = scale.unwrap_or_else(|| profile.default_screen_scale());
-        let canvas = Self::init_canvas(
-            &video,
-            u16::from(profile.screen_width()) * u16::from(scale),
-            u16::from(profile.screen_height()) * u16::from(scale),
-        )?;
+        let canvas = Self::init_canvas(&video, profile, scale, display, palette)?;
+
+        // the whole native framebuffer is streamed into a single texture each
+        // frame, and scaled up to the window by the renderer
+        hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let texture = canvas
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                u32::from(profile.screen_width()),
+                u32::from(profile.screen_height()),
+            )
+            .map_err(Error::new)?;
 
         let audio = audio::Audio::new(&sdl_context)?;
 
+        let controllers = controller::Controllers::new(
+            sdl_context.game_controller().map_err(Error::msg)?,
+            mapping,
+        );
+
         let events = sdl_context.event_pump().map_err(Error::msg)?;
 
+        // the overlay opens to the right of the main window, and the memory
+        // viewer to the left
+        let (x, y) = canvas.window().position();
+        let (width, _) = canvas.window().size();
+        let overlay = if debug_overlay {
+            let right = x.saturating_add(i32::try_from(width)?);
+            Some(Overlay::new(&video, (right, y))?)
+        } else {
+            None
+        };
+        let memory_viewer = if memory_viewer {
+            Some(MemoryViewer::new(&video, (x, y))?)
+        } else {
+            None
+        };
+
         Ok(Self {
             profile,
-            scale,
             canvas,
+            texture,
             audio,
+            controllers,
+            palette: palette::Selector::new(palette),
+            redraw: false,
             events,
+            overlay,
+            memory_viewer,
         })
     }
 
     fn init_canvas(
         video_subsys: &VideoSubsystem,
-        width: u16,
-        height: u16,
+        profile: profile::Profile,
+        scale: Option<u8>,
+        display: Option<u8>,
+        palette: palette::Palette,
     ) -> Result<Canvas<Window>> {
-        // TODO iterate through all displays, work out which one mouse is on,
-        // use that to determine window size limit
-        let dm = video_subsys.desktop_display_mode(0).map_err(Error::msg)?;
+        let width = u32::from(profile.screen_width());
+        let height = u32::from(profile.screen_height());
 
-        if i32::from(width) > dm.w || i32::from(height) > dm.h {
+        let mut window = video_subsys
+            .window("chipper", width, height)
+            .position_centered()
+            .resizable()
+            .hidden()
+            .build()
+            .map_err(Error::new)?;
+
+        // limit the initial size to the display the window will appear on
+        let display_index = match display {
+            Some(d) => i32::from(d),
+            None => window.display_index().map_err(Error::msg)?,
+        };
+        let bounds = video_subsys
+            .display_usable_bounds(display_index)
+            .map_err(Error::msg)?;
+
+        let max_scale = (bounds.width() / width).min(bounds.height() / height);
+        if max_scale == 0 {
             return Err(anyhow!("Window too large"));
         }
+        let scale =
+            u32::from(scale.unwrap_or_else(|| profile.default_screen_scale())).min(max_scale);
 
-        let window = video_subsys
-            .window("chipper", u32::from(width), u32::from(height))
-            .position_centered()
-            .build()
+        let placement = Rect::from_center(bounds.center(), width * scale, height * scale);
+        window
+            .set_size(placement.width(), placement.height())
             .map_err(Error::new)?;
+        window.set_position(
+            WindowPos::Positioned(placement.x()),
+            WindowPos::Positioned(placement.y()),
+        );
+        window.show();
 
         let mut canvas = window
             .into_canvas()
@@ -75,49 +190,299 @@ impl Hardware {
             .build()
             .map_err(Error::new)?;
 
-        canvas.set_draw_color(BLACK);
+        // scale by whole multiples of the native resolution only, letterboxing
+        // any remaining space
+        canvas.set_logical_size(width, height).map_err(Error::new)?;
+        canvas.set_integer_scale(true).map_err(Error::msg)?;
+
+        canvas.set_draw_color(palette.background());
         canvas.clear();
         canvas.present();
         Ok(canvas)
     }
 
-    pub(super) fn refresh_graphics(&mut self, gfx: &BitVec, res_scale: u8) -> Result<()> {
-        let rect_scale = u32::from(self.scale * res_scale);
-        let mut rect = Rect::new(0, 0, rect_scale, rect_scale);
-        let sw = u16::from(self.profile.screen_width());
-        let s = i32::from(self.scale * res_scale);
-
-        for yline in 0..u16::from(self.profile.screen_height()) {
-            for xline in 0..sw {
-                self.canvas
-                    .set_draw_color(if gfx[usize::from((yline * sw) + xline)] {
-                        WHITE
-                    } else {
-                        BLACK
-                    });
-                rect.set_x(i32::from(xline) * s);
-                rect.set_y(i32::from(yline) * s);
-                self.canvas.fill_rect(rect).map_err(Error::msg)?;
+    fn refresh_graphics(&mut self, gfx: &BitVec, res_scale: u8) -> Result<()> {
+        let sw = self.profile.screen_width();
+        let sh = self.profile.screen_height();
+        let palette = self.palette.current();
+
+        self.texture
+            .with_lock(None, |buffer, pitch| {
+                for (row, line) in gfx.chunks(usize::from(sw)).zip(buffer.chunks_mut(pitch)) {
+                    for (bit, pixel) in row.iter().zip(line.chunks_exact_mut(3)) {
+                        let colour = palette.colour(usize::from(*bit));
+                        pixel.copy_from_slice(&[colour.r, colour.g, colour.b]);
+                    }
+                }
+            })
+            .map_err(Error::msg)?;
+
+        // lores modes only draw into the top-left quarter of the buffer
+        let source = Rect::new(0, 0, u32::from(sw / res_scale), u32::from(sh / res_scale));
+
+        self.canvas.set_draw_color(palette.background());
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, source, None)
+            .map_err(Error::msg)?;
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    /// Handles the window hotkeys and events, returning any input they produce.
+    fn handle_window_event(&mut self, event: &Event) -> Option<Input> {
+        match event {
+            Event::KeyDown {
+                scancode: Some(Scancode::F11),
+                repeat: false,
+                ..
+            } => Some(Input::ToggleRecording),
+            Event::KeyDown {
+                scancode: Some(Scancode::F12),
+                keymod,
+                repeat: false,
+                ..
+            } => {
+                // F12 captures at native resolution, Shift+F12 at the window scale
+                let scale = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    let (out_w, out_h) = self.canvas.output_size().unwrap_or_default();
+                    let (log_w, log_h) = self.canvas.logical_size();
+                    u8::try_from((out_w / log_w.max(1)).min(out_h / log_h.max(1)))
+                        .unwrap_or(u8::MAX)
+                        .max(1)
+                } else {
+                    1
+                };
+                Some(Input::Screenshot { scale })
             }
+            Event::KeyDown {
+                scancode: Some(Scancode::Return),
+                keymod,
+                repeat: false,
+                ..
+            } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                // Alt+Enter toggles fullscreen on the display the window is on
+                let window = self.canvas.window_mut();
+                let mode = match window.fullscreen_state() {
+                    FullscreenType::Off => FullscreenType::Desktop,
+                    FullscreenType::True | FullscreenType::Desktop => FullscreenType::Off,
+                };
+                if let Err(e) = window.set_fullscreen(mode) {
+                    eprintln!("unable to toggle fullscreen: {e}");
+                }
+                self.redraw = true;
+                None
+            }
+            Event::Window {
+                window_id,
+                win_event: WindowEvent::Close,
+                ..
+            } => {
+                // with the debug windows open, closing a window doesn't quit
+                // by itself
+                if self
+                    .overlay
+                    .as_ref()
+                    .is_some_and(|o| o.window_id() == *window_id)
+                {
+                    self.overlay = None;
+                    None
+                } else if self
+                    .memory_viewer
+                    .as_ref()
+                    .is_some_and(|m| m.window_id() == *window_id)
+                {
+                    self.memory_viewer = None;
+                    None
+                } else {
+                    Some(Input::Quit)
+                }
+            }
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                ..
+            } => {
+                self.redraw = true;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Translates keyboard events (including those synthesized from controllers)
+/// into CHIP-8 key input.
+fn key_input(event: &Event) -> Option<Input> {
+    match event {
+        Event::Quit { .. }
+        | Event::KeyDown {
+            scancode: Some(Scancode::Escape),
+            ..
+        } => Some(Input::Quit),
+        Event::KeyDown {
+            repeat: false,
+            scancode: Some(code),
+            ..
+        }
+        | Event::KeyUp {
+            repeat: false,
+            scancode: Some(code),
+            ..
+        } => scancode_key(*code).map(|key| Input::Key {
+            key,
+            pressed: matches!(event, Event::KeyDown { .. }),
+        }),
+        _ => None,
+    }
+}
+
+impl frontend::Display for Hardware {
+    fn present(&mut self, gfx: &BitVec, res_scale: u8, changed: bool) -> Result<()> {
+        // the window may need redrawing even though the emulated display has
+        // not changed, e.g. after a resize or palette swap
+        let redraw = self.palette.take_changed() | std::mem::take(&mut self.redraw);
+
+        if changed || redraw {
+            self.refresh_graphics(gfx, res_scale)?;
         }
-        self.canvas.present();
 
         Ok(())
     }
 
-    pub(super) fn sound_stop(&mut self) {
-        if self.audio.playing() {
-            self.audio.pause();
+    fn palette(&self) -> palette::Palette {
+        self.palette.current()
+    }
+
+    fn inspect(&mut self, chip8: &chip8::Chip8) -> Result<()> {
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.draw(chip8)?;
         }
+
+        if let Some(viewer) = self.memory_viewer.as_mut() {
+            viewer.draw(chip8)?;
+        }
+
+        Ok(())
     }
+}
 
-    pub(super) fn sound_start(&mut self) {
-        if self.audio.paused() {
+impl frontend::Sound for Hardware {
+    fn set_sounding(&mut self, sounding: bool) {
+        if sounding && self.audio.paused() {
             self.audio.play();
+        } else if !sounding && self.audio.playing() {
+            self.audio.pause();
+        }
+    }
+}
+
+impl frontend::InputSource for Hardware {
+    fn poll(&mut self) -> Result<Vec<Input>> {
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        let mut inputs = Vec::new();
+
+        for event in events {
+            // keys typed into the memory viewer edit memory rather than
+            // pressing CHIP-8 keys
+            if let (
+                Some(viewer),
+                Event::KeyDown {
+                    window_id,
+                    keycode: Some(key),
+                    ..
+                },
+            ) = (self.memory_viewer.as_mut(), &event)
+            {
+                if *window_id == viewer.window_id() {
+                    inputs.extend(viewer.key(*key));
+                    continue;
+                }
+            }
+
+            if self.palette.handle_event(&event) {
+                continue;
+            }
+
+            if let Some(input) = self.handle_window_event(&event) {
+                inputs.push(input);
+                continue;
+            }
+
+            inputs.extend(
+                self.controllers
+                    .translate(event)
+                    .iter()
+                    .filter_map(key_input),
+            );
+        }
+
+        Ok(inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::event::Event;
+    use sdl2::keyboard::Mod;
+    use sdl2::keyboard::Scancode;
+
+    use super::key_input;
+    use super::key_scancode;
+    use crate::frontend::Input;
+
+    fn key_event(scancode: Scancode, down: bool) -> Event {
+        if down {
+            Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: None,
+                scancode: Some(scancode),
+                keymod: Mod::empty(),
+                repeat: false,
+            }
+        } else {
+            Event::KeyUp {
+                timestamp: 0,
+                window_id: 0,
+                keycode: None,
+                scancode: Some(scancode),
+                keymod: Mod::empty(),
+                repeat: false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_quit_event_is_quit_input() {
+        assert_eq!(key_input(&Event::Quit { timestamp: 0 }), Some(Input::Quit));
+    }
+
+    #[test]
+    fn test_keydown_escape_is_quit_input() {
+        assert_eq!(
+            key_input(&key_event(Scancode::Escape, true)),
+            Some(Input::Quit)
+        );
+    }
+
+    #[test]
+    fn test_keypad_events_are_key_input() {
+        for key in 0u8..16 {
+            let code = key_scancode(key);
+            assert!(code.is_some());
+
+            for pressed in [true, false] {
+                assert_eq!(
+                    code.and_then(|c| key_input(&key_event(c, pressed))),
+                    Some(Input::Key { key, pressed })
+                );
+            }
         }
     }
 
-    pub(super) fn event_iter(&mut self) -> EventPollIterator<'_> {
-        self.events.poll_iter()
+    #[test]
+    fn test_other_keys_are_ignored() {
+        assert_eq!(key_input(&key_event(Scancode::P, true)), None);
     }
 }
@@ -0,0 +1,318 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+
+use crate::emulator::Captures;
+use crate::emulator::Emulator;
+use crate::frontend;
+use crate::frontend::Display;
+use crate::frontend::Input;
+use crate::palette;
+use crate::screenshot;
+use crate::ProcessType;
+use crate::Target;
+
+// how long each scripted key press is held down for
+const KEY_HOLD_FRAMES: u32 = 5;
+
+/// A scripted key press: the key goes down at the start of `frame` and is
+/// released `KEY_HOLD_FRAMES` later (or on the last frame there is).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) struct KeyPress {
+    frame: u32,
+    key: u8,
+}
+
+impl KeyPress {
+    /// The key going down, then coming back up.
+    pub(super) fn events(self) -> [KeyEvent; 2] {
+        [
+            KeyEvent {
+                frame: self.frame,
+                key: self.key,
+                pressed: true,
+            },
+            KeyEvent {
+                frame: self.frame.saturating_add(KEY_HOLD_FRAMES),
+                key: self.key,
+                pressed: false,
+            },
+        ]
+    }
+}
+
+/// A scripted key going down or up at the start of `frame`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) struct KeyEvent {
+    frame: u32,
+    key: u8,
+    pressed: bool,
+}
+
+fn parse_key(value: &str) -> Option<u8> {
+    u8::from_str_radix(value, 16).ok().filter(|k| *k <= 0xF)
+}
+
+/// Parses a key event file: one `FRAME KEY down|up` event per line, with the
+/// key as a hex digit. Blank lines and lines starting with `#` are ignored.
+pub(super) fn parse_key_events(contents: &str) -> Result<Vec<KeyEvent>> {
+    let mut events = Vec::new();
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [frame, key, state] = fields[..] else {
+            return Err(anyhow!("line {}: expected 'FRAME KEY down|up'", num + 1));
+        };
+
+        events.push(KeyEvent {
+            frame: frame
+                .parse()
+                .with_context(|| format!("line {}: invalid frame '{frame}'", num + 1))?,
+            key: parse_key(key).ok_or_else(|| anyhow!("line {}: invalid key '{key}'", num + 1))?,
+            pressed: match state.to_ascii_lowercase().as_str() {
+                "down" => true,
+                "up" => false,
+                _ => return Err(anyhow!("line {}: expected down or up", num + 1)),
+            },
+        });
+    }
+
+    Ok(events)
+}
+
+/// Parses a `FRAME:KEY` key press, with the key as a hex digit.
+pub(super) fn parse_key_press(value: &str) -> Result<KeyPress> {
+    let (frame, key) = value
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid key press '{value}', expected FRAME:KEY"))?;
+
+    Ok(KeyPress {
+        frame: frame
+            .parse()
+            .with_context(|| format!("invalid frame in '{value}'"))?,
+        key: parse_key(key).ok_or_else(|| anyhow!("invalid key in '{value}'"))?,
+    })
+}
+
+/// When a headless run should stop. The run ends as soon as any limit is hit.
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct Limits {
+    pub(super) frames: Option<u32>,
+    pub(super) until_pc: Option<u16>,
+    /// Stop when the program exits, or stops advancing (e.g. a jump to itself,
+    /// or waiting for a key that will never arrive)
+    pub(super) until_halt: bool,
+    /// The frame of the last scripted key event. Until then, a program
+    /// waiting for a key hasn't halted.
+    pub(super) last_input: Option<u32>,
+}
+
+impl Limits {
+    fn is_bounded(self) -> bool {
+        self.frames.is_some() || self.until_pc.is_some() || self.until_halt
+    }
+
+    /// Notes when the last of `events` arrives.
+    pub(super) fn with_events(self, events: &[KeyEvent]) -> Self {
+        Self {
+            last_input: events.iter().map(|e| e.frame).max(),
+            ..self
+        }
+    }
+
+    /// True if the cycle in `frame` that moved the program counter from `pc`
+    /// to `new_pc` hit a limit.
+    pub(super) fn hit(self, pc: u16, new_pc: u16, frame: u32, waiting_for_key: bool) -> bool {
+        let input_to_come = waiting_for_key && self.last_input.is_some_and(|last| frame < last);
+        self.until_pc == Some(new_pc) || (self.until_halt && new_pc == pc && !input_to_come)
+    }
+}
+
+/// Parses a hex address, with or without a leading `0x`.
+pub(super) fn parse_address(value: &str) -> Result<u16> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+
+    u16::from_str_radix(hex, 16).with_context(|| format!("invalid address '{value}'"))
+}
+
+/// Runs a ROM without a window or audio device, in 60 Hz frames but without
+/// any real-time delay, then prints or dumps the final display.
+pub(super) fn run(
+    target: Target,
+    filename: &str,
+    limits: Limits,
+    keys: &[KeyPress],
+    captures: Captures,
+    dump: Option<&Path>,
+    palette: palette::Palette,
+) -> Result<()> {
+    if !limits.is_bounded() {
+        return Err(anyhow!(
+            "Headless mode needs --frames, --until-pc or --until-halt"
+        ));
+    }
+
+    let events: Vec<KeyEvent> = keys.iter().flat_map(|k| k.events()).collect();
+    let limits = limits.with_events(&events);
+
+    let mut emulator = Emulator::new(target, Headless::new(palette, events), captures)?
+        .with_limits(limits)
+        .unthrottled();
+    emulator.process(ProcessType::Run, filename)?;
+
+    write_display(&emulator, dump)
+}
+
+/// Writes the final display to `path` as PNG (or PBM if the filename ends in
+/// `.pbm`), or prints it to stdout as text if no path is given.
+fn write_display(emulator: &Emulator<Headless>, path: Option<&Path>) -> Result<()> {
+    let profile = emulator.profile();
+    let gfx = emulator.chip8().graphics();
+    let res_scale = emulator.chip8().resolution_scale();
+
+    match path {
+        Some(p) if p.extension().is_some_and(|e| e.eq_ignore_ascii_case("pbm")) => {
+            screenshot::write_pbm(p, gfx, profile, res_scale)
+        }
+        Some(p) => {
+            screenshot::Image::render(gfx, profile, res_scale, 1, emulator.frontend().palette())
+                .write_png(p)
+        }
+        None => {
+            print!("{}", screenshot::ascii(gfx, profile, res_scale));
+            Ok(())
+        }
+    }
+}
+
+/// A frontend with no window or audio device, whose only input is a script of
+/// key events.
+#[must_use]
+pub(super) struct Headless {
+    palette: palette::Palette,
+    events: Vec<KeyEvent>,
+    frame: u32,
+}
+
+impl Headless {
+    pub(super) fn new(palette: palette::Palette, events: Vec<KeyEvent>) -> Self {
+        Self {
+            palette,
+            events,
+            frame: 0,
+        }
+    }
+}
+
+impl frontend::Display for Headless {
+    fn present(&mut self, _gfx: &BitVec, _res_scale: u8, _changed: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn palette(&self) -> palette::Palette {
+        self.palette
+    }
+}
+
+impl frontend::Sound for Headless {
+    fn set_sounding(&mut self, _sounding: bool) {}
+}
+
+impl frontend::InputSource for Headless {
+    fn poll(&mut self) -> Result<Vec<Input>> {
+        // input is polled at the end of each frame, so applies from the next;
+        // events for frame 0 land as early as they can, before frame 1
+        self.frame += 1;
+
+        Ok(self
+            .events
+            .iter()
+            .filter(|e| e.frame.max(1) == self.frame)
+            .map(|e| Input::Key {
+                key: e.key,
+                pressed: e.pressed,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use super::parse_address;
+    use super::parse_key_events;
+    use super::parse_key_press;
+    use super::KeyEvent;
+    use super::KeyPress;
+
+    #[test]
+    fn test_parse_address() -> Result<(), Error> {
+        assert_eq!(parse_address("0x2A4")?, 0x2A4);
+        assert_eq!(parse_address("3ff")?, 0x3FF);
+        assert!(parse_address("0xZZ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_key_events() -> Result<(), Error> {
+        assert_eq!(
+            parse_key_events("# start\n60 5 down\n\n65 5 UP\n")?,
+            vec![
+                KeyEvent {
+                    frame: 60,
+                    key: 5,
+                    pressed: true
+                },
+                KeyEvent {
+                    frame: 65,
+                    key: 5,
+                    pressed: false
+                }
+            ]
+        );
+        assert!(parse_key_events("60 5").is_err());
+        assert!(parse_key_events("60 G down").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_key_press() -> Result<(), Error> {
+        assert_eq!(
+            parse_key_press("120:a")?,
+            KeyPress {
+                frame: 120,
+                key: 0xA
+            }
+        );
+        assert!(parse_key_press("120").is_err());
+        assert!(parse_key_press("120:10").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_press_events() -> Result<(), Error> {
+        // when
+        let press = parse_key_press("4294967294:5")?;
+
+        // then
+        let [down, up] = press.events();
+
+        // verify
+        assert_eq!(down.frame, u32::MAX - 1);
+        assert_eq!(up.frame, u32::MAX);
+        assert!(down.pressed && !up.pressed);
+        Ok(())
+    }
+}
@@ -6,17 +6,44 @@
     // clippy::indexing_slicing
 )]
 
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 use clap::value_parser;
 use clap::Parser;
+use clap::Subcommand;
 use clap::ValueEnum;
+use sdl2::pixels::Color;
 
 mod audio;
+mod cheats;
 mod chip8;
+mod controller;
+mod difftrace;
+mod disassembler;
 mod emulator;
+mod frontend;
+mod gdb;
+mod gym;
 mod hardware;
+mod headless;
+mod memview;
+mod overlay;
+mod palette;
 mod profile;
+mod profiler;
+mod recorder;
+mod screenshot;
+mod script;
+mod timing;
+mod trace;
+mod tui;
 mod util;
+mod wav;
 
 #[derive(ValueEnum, Debug, Copy, Clone)]
 pub(crate) enum ProcessType {
@@ -24,9 +51,23 @@ pub(crate) enum ProcessType {
     Run,
 }
 
+#[derive(ValueEnum, Debug, Copy, Clone)]
+pub(crate) enum Frontend {
+    /// Window, audio and input through SDL
+    Sdl,
+    /// Text in the terminal, for use over SSH
+    Tui,
+}
+
 #[derive(ValueEnum, Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub(crate) enum Target {
     Chip8,
+    HiresChip8,
+    #[value(name = "eti-660")]
+    Eti660,
+    Chip48,
+    #[value(name = "super-chip-10")]
+    SuperChip10,
     SuperChipLegacy,
     SuperChip,
     XoChip,
@@ -38,30 +79,312 @@ pub(crate) enum Action {
     Invalid,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a binary (.bin) trace file as text
+    TraceDump {
+        /// Trace file to read
+        path: PathBuf,
+    },
+    /// Run a ROM and compare each instruction with a reference trace
+    DiffTrace {
+        /// Reference trace, one `NAME=hex` state line per instruction
+        reference: PathBuf,
+
+        /// ROM filename to load
+        #[clap(short, long)]
+        file: String,
+
+        /// Target architecture to emulate
+        #[clap(short, long, value_enum, default_value_t = Target::Chip8)]
+        target: Target,
+
+        /// Seed for the random number generator used by RND
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Key events to replay, one `FRAME KEY down|up` per line
+        #[clap(long)]
+        keys: Option<PathBuf>,
+    },
+    /// Run a ROM as a reinforcement-learning environment, driven by commands
+    /// on stdin
+    Gym(gym::Options),
+}
+
 /// Simple CHIP-8 emulator
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
-    /// Scale factor for the window
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Initial scale factor for the window (Alt+Enter toggles fullscreen)
     #[clap(short, long, value_parser = value_parser!(u8).range(1..64))]
     scale: Option<u8>,
 
+    /// Display to open the window on (defaults to the primary display)
+    #[clap(short, long, value_parser)]
+    display: Option<u8>,
+
+    /// Open a second window showing the registers, stack, timers, keys and
+    /// the instructions around PC
+    #[clap(long, action)]
+    debug_overlay: bool,
+
+    /// Read cheat commands (memory searches and freezes) from stdin while
+    /// running in a window
+    #[clap(long, action)]
+    cheat_console: bool,
+
+    /// Headless: apply the ROM's cheat file, as a window always does
+    #[clap(long, action)]
+    cheats: bool,
+
+    /// Open a window showing memory as hex or sprites, where typing hex digits
+    /// edits the byte under the cursor
+    #[clap(long, action)]
+    memory_viewer: bool,
+
     /// Target architecture to emulate
     #[clap(short, long, value_enum, default_value_t = Target::Chip8)]
     target: Target,
 
+    /// Where the display is shown and input comes from
+    #[clap(long, value_enum, default_value_t = Frontend::Sdl)]
+    frontend: Frontend,
+
     /// How emulator cycles will be executed
     #[clap(short, long, value_enum, default_value_t = ProcessType::Run)]
     process_type: ProcessType,
 
-    /// ROM filename to load
+    /// Game controller mapping file (defaults to <ROM>.controller if present)
     #[clap(short, long, value_parser)]
-    file: String,
+    controller: Option<String>,
+
+    /// Named colour palette ('P' cycles palettes while running)
+    #[clap(long, value_enum, default_value_t = palette::Preset::Default)]
+    palette: palette::Preset,
+
+    /// Palette file (defaults to <ROM>.palette if present)
+    #[clap(long, value_parser)]
+    palette_file: Option<String>,
+
+    /// Background colour as RRGGBB hex
+    #[clap(long, value_parser = palette::parse_colour)]
+    bg: Option<Color>,
+
+    /// Foreground colour as RRGGBB hex
+    #[clap(long, value_parser = palette::parse_colour)]
+    fg: Option<Color>,
+
+    /// Write a PNG screenshot of the display to this file on exit (F12 takes one while running)
+    #[clap(long, value_parser)]
+    screenshot_on_exit: Option<PathBuf>,
+
+    /// Pixels per display pixel for the exit screenshot
+    #[clap(long, value_parser = value_parser!(u8).range(1..64), default_value_t = 1)]
+    screenshot_scale: u8,
+
+    /// Record gameplay to this file, as an animated GIF if it ends in .gif or
+    /// as a numbered PNG sequence otherwise (F11 toggles recording while running)
+    #[clap(long, value_parser)]
+    record: Option<PathBuf>,
+
+    /// Pixels per display pixel for recordings
+    #[clap(long, value_parser = value_parser!(u8).range(1..64), default_value_t = 1)]
+    record_scale: u8,
+
+    /// Capture the audio output to this WAV file
+    #[clap(long, value_parser)]
+    record_audio: Option<PathBuf>,
+
+    /// Run without a window or audio, then print or dump the final display
+    #[clap(long, action)]
+    headless: bool,
+
+    /// Headless: stop after this many 60 Hz frames
+    #[clap(long, value_parser)]
+    frames: Option<u32>,
+
+    /// Headless: stop when the program counter reaches this (hex) address
+    #[clap(long, value_parser = headless::parse_address)]
+    until_pc: Option<u16>,
+
+    /// Headless: stop when the program exits or stops advancing
+    #[clap(long, action)]
+    until_halt: bool,
+
+    /// Headless: press a key (hex) at a frame, e.g. --press 60:1 (repeatable)
+    #[clap(long, value_parser = headless::parse_key_press)]
+    press: Vec<headless::KeyPress>,
+
+    /// Headless: write the final display to this PNG or PBM file instead of printing it
+    #[clap(long, value_parser)]
+    dump: Option<PathBuf>,
+
+    /// Write a line per executed instruction to this file, in a compact
+    /// binary format if it ends in .bin
+    #[clap(long, value_parser)]
+    trace: Option<PathBuf>,
+
+    /// Only trace instructions in this (hex) address range, e.g. 200-2FF
+    #[clap(long, value_parser = trace::parse_pc_range)]
+    trace_pc: Option<RangeInclusive<u16>>,
+
+    /// Only trace these opcode classes (first hex digit), e.g. 8,D
+    #[clap(long, value_parser = trace::parse_class, value_delimiter = ',')]
+    trace_class: Vec<u8>,
+
+    /// Only trace instructions in this frame range, e.g. 60-120
+    #[clap(long, value_parser = trace::parse_frame_range)]
+    trace_frames: Option<RangeInclusive<u32>>,
+
+    /// Print a report of the hottest addresses, opcode classes and
+    /// subroutines to stderr on exit
+    #[clap(long, action)]
+    profile: bool,
+
+    /// Write a folded-stack profile to this file on exit, for flamegraph tools
+    #[clap(long, value_parser)]
+    profile_folded: Option<PathBuf>,
+
+    /// Profile: report frames drawing more than this many sprites
+    #[clap(long, value_parser, default_value_t = 10)]
+    profile_draws: u32,
+
+    /// Run this Rhai script alongside the ROM, with callbacks on each frame,
+    /// PC address or memory write
+    #[clap(long, value_parser)]
+    script: Option<PathBuf>,
+
+    /// Wait for a gdb-compatible debugger to attach on this address, e.g.
+    /// 127.0.0.1:1234
+    #[clap(long, value_parser)]
+    gdb: Option<SocketAddr>,
+
+    /// How long instructions take: the same for each, or as on the COSMAC VIP
+    /// (chip8 only)
+    #[clap(long, value_enum, default_value_t = timing::Timing::Fixed)]
+    timing: timing::Timing,
+
+    /// What waiting for a key (FX0A) makes of a key already held down when the
+    /// wait starts
+    #[clap(long, value_enum, default_value_t = chip8::HeldKeys::Ignore)]
+    held_keys: chip8::HeldKeys,
+
+    /// ROM filename to load
+    #[clap(short, long, value_parser, required = true)]
+    file: Option<String>,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    emulator::Emulator::new(args.scale, args.target)
-        .and_then(|mut e| e.process(args.process_type, &args.file))
+    match &args.command {
+        Some(Command::TraceDump { path }) => return trace::dump(path),
+        Some(Command::DiffTrace {
+            reference,
+            file,
+            target,
+            seed,
+            keys,
+        }) => return difftrace::run(*target, file, reference, *seed, keys.as_deref()),
+        Some(Command::Gym(options)) => return gym::run(options.clone()),
+        None => {}
+    }
+
+    let file = args.file.context("A ROM --file is required")?;
+
+    let palette = palette::Palette::load(
+        args.palette,
+        args.palette_file.as_deref(),
+        &file,
+        [args.bg, args.fg],
+    )?;
+
+    let captures = emulator::Captures {
+        screenshot_on_exit: args.screenshot_on_exit.map(|path| screenshot::Output {
+            path,
+            scale: args.screenshot_scale,
+        }),
+        record: args.record.map(|path| screenshot::Output {
+            path,
+            scale: args.record_scale,
+        }),
+        audio: args.record_audio,
+        trace: args.trace.map(|path| trace::Options {
+            path,
+            filter: trace::Filter {
+                pc: args.trace_pc,
+                classes: args.trace_class,
+                frames: args.trace_frames,
+            },
+        }),
+        profile: (args.profile || args.profile_folded.is_some()).then_some(profiler::Options {
+            report: args.profile,
+            folded: args.profile_folded,
+            draw_limit: args.profile_draws,
+        }),
+        script: args.script,
+        gdb: args.gdb,
+        timing: args.timing,
+        held_keys: args.held_keys,
+        cheats: !args.headless || args.cheats,
+    };
+
+    if args.headless && args.cheat_console {
+        return Err(anyhow!(
+            "The cheat console needs a window, and isn't available with --headless"
+        ));
+    }
+
+    if args.headless {
+        let limits = headless::Limits {
+            frames: args.frames,
+            until_pc: args.until_pc,
+            until_halt: args.until_halt,
+            last_input: None,
+        };
+
+        return headless::run(
+            args.target,
+            &file,
+            limits,
+            &args.press,
+            captures,
+            args.dump.as_deref(),
+            palette,
+        );
+    }
+
+    match args.frontend {
+        Frontend::Sdl => {
+            let mapping = controller::Mapping::load(args.controller.as_deref(), &file)?;
+            let hardware = hardware::Hardware::new(
+                args.scale,
+                args.display,
+                profile::for_target(args.target)?,
+                mapping,
+                palette,
+                args.debug_overlay,
+                args.memory_viewer,
+            )?;
+
+            let mut emulator = emulator::Emulator::new(args.target, hardware, captures)?;
+            if args.cheat_console {
+                emulator = emulator.with_cheat_console();
+            }
+            emulator.process(args.process_type, &file)
+        }
+        Frontend::Tui if args.cheat_console => Err(anyhow!(
+            "The cheat console reads stdin, which the terminal frontend uses for input"
+        )),
+        Frontend::Tui => {
+            emulator::Emulator::new(args.target, tui::Tui::new(args.target, palette)?, captures)?
+                .process(args.process_type, &file)
+        }
+    }
 }
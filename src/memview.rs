@@ -0,0 +1,484 @@
+use anyhow::Error;
+use anyhow::Result;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::video::WindowPos;
+use sdl2::VideoSubsystem;
+
+use crate::chip8;
+use crate::frontend::Input;
+use crate::overlay;
+use crate::overlay::CELL_HEIGHT;
+use crate::overlay::CELL_WIDTH;
+
+const COLUMNS: u32 = 54;
+const ROWS: u32 = 36;
+
+// rows of text between the header and the footer
+const FIRST_ROW: i32 = 2;
+const HEX_ROWS: u16 = 32;
+
+const BYTES_PER_ROW: u16 = 16;
+
+// the sprite view shows memory as columns of 8-pixel rows, one byte each,
+// in bands labelled with their first address
+const SPRITE_PIXEL: i32 = 2;
+const COLUMN_BYTES: u16 = 16;
+const BAND_COLUMNS: u16 = 14;
+const BANDS: u16 = 6;
+const LABEL_WIDTH: i32 = 30;
+const COLUMN_WIDTH: i32 = 8 * SPRITE_PIXEL + 4;
+const BAND_HEIGHT: i32 = 9 + 16 * SPRITE_PIXEL + 4;
+
+const CURSOR: Color = Color::RGB(0x50, 0x50, 0x68);
+const INDEX: Color = Color::RGB(0x40, 0xC0, 0xFF);
+const WRITTEN: Color = Color::RGB(0xFF, 0x60, 0x60);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum View {
+    Hex,
+    Sprites,
+}
+
+/// How a byte is highlighted, in order of priority.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Mark {
+    Pc,
+    Index,
+    Written,
+    Plain,
+}
+
+impl Mark {
+    fn colour(self) -> Color {
+        match self {
+            Mark::Pc => overlay::HIGHLIGHT,
+            Mark::Index => INDEX,
+            Mark::Written => WRITTEN,
+            Mark::Plain => overlay::TEXT,
+        }
+    }
+}
+
+/// The state of the editor, apart from its window.
+#[derive(Debug)]
+struct Editor {
+    view: View,
+    cursor: u16,
+    // the first address shown
+    top: u16,
+    // the high nibble typed so far
+    pending: Option<u8>,
+    memory: Vec<u8>,
+    written: Vec<bool>,
+    pc: u16,
+    i: u16,
+}
+
+impl Editor {
+    fn new() -> Self {
+        Self {
+            view: View::Hex,
+            cursor: 0x200,
+            top: 0x200,
+            pending: None,
+            memory: Vec::new(),
+            written: Vec::new(),
+            pc: 0x200,
+            i: 0,
+        }
+    }
+
+    /// Takes a copy of memory, noting the bytes that changed since the last
+    /// frame.
+    fn update(&mut self, chip8: &chip8::Chip8) {
+        let memory = chip8.memory();
+
+        if self.memory.len() == memory.len() {
+            for ((written, old), new) in self.written.iter_mut().zip(&self.memory).zip(memory) {
+                *written = old != new;
+            }
+        } else {
+            self.written = vec![false; memory.len()];
+        }
+        self.memory.clear();
+        self.memory.extend_from_slice(memory);
+
+        let state = chip8.state();
+        self.pc = state.pc;
+        self.i = state.i;
+    }
+
+    fn mark(&self, address: u16) -> Mark {
+        if address == self.pc || address == self.pc.wrapping_add(1) {
+            Mark::Pc
+        } else if address == self.i {
+            Mark::Index
+        } else if self.written.get(usize::from(address)) == Some(&true) {
+            Mark::Written
+        } else {
+            Mark::Plain
+        }
+    }
+
+    /// The number of bytes shown at once.
+    fn page(&self) -> u16 {
+        match self.view {
+            View::Hex => HEX_ROWS * BYTES_PER_ROW,
+            View::Sprites => BANDS * BAND_COLUMNS * COLUMN_BYTES,
+        }
+    }
+
+    /// The addresses shown, from the top of the view. These are counted in
+    /// `u32` so that a view ending at 0xFFFF doesn't overflow.
+    fn visible(&self) -> impl Iterator<Item = u16> {
+        let end = u32::from(self.top) + u32::from(self.page());
+        let end = end.min(u32::try_from(self.memory.len()).unwrap_or(0));
+        (u32::from(self.top)..end).filter_map(|address| u16::try_from(address).ok())
+    }
+
+    fn move_to(&mut self, address: i64) {
+        let last = i64::try_from(self.memory.len()).unwrap_or(0) - 1;
+        self.cursor = u16::try_from(address.clamp(0, last.max(0))).unwrap_or(u16::MAX);
+        self.pending = None;
+
+        // keep the cursor on screen, scrolling by whole rows
+        let row = u32::from(self.cursor) & !u32::from(BYTES_PER_ROW - 1);
+        let page = u32::from(self.page());
+        if u32::from(self.cursor) < u32::from(self.top) {
+            self.top = u16::try_from(row).unwrap_or(0);
+        } else if u32::from(self.cursor) >= u32::from(self.top) + page {
+            self.top =
+                u16::try_from((row + u32::from(BYTES_PER_ROW)).saturating_sub(page)).unwrap_or(0);
+        }
+    }
+
+    fn move_by(&mut self, delta: i64) {
+        self.move_to(i64::from(self.cursor) + delta);
+    }
+
+    /// Handles a key pressed in the editor window, returning the byte to
+    /// write once two hex digits have been typed.
+    fn key(&mut self, key: Keycode) -> Option<Input> {
+        let (across, down) = match self.view {
+            View::Hex => (1, i64::from(BYTES_PER_ROW)),
+            View::Sprites => (i64::from(COLUMN_BYTES), 1),
+        };
+
+        match key {
+            Keycode::LEFT => self.move_by(-across),
+            Keycode::RIGHT => self.move_by(across),
+            Keycode::UP => self.move_by(-down),
+            Keycode::DOWN => self.move_by(down),
+            Keycode::PAGEUP => self.move_by(-i64::from(self.page())),
+            Keycode::PAGEDOWN => self.move_by(i64::from(self.page())),
+            Keycode::HOME => self.move_to(i64::from(self.pc)),
+            Keycode::END => self.move_to(i64::from(self.i)),
+            Keycode::TAB => {
+                self.view = match self.view {
+                    View::Hex => View::Sprites,
+                    View::Sprites => View::Hex,
+                };
+                self.move_by(0);
+            }
+            Keycode::ESCAPE | Keycode::BACKSPACE => self.pending = None,
+            _ => {
+                let digit = u32::try_from(key.into_i32())
+                    .ok()
+                    .and_then(char::from_u32)
+                    .and_then(|c| c.to_digit(16))
+                    .and_then(|d| u8::try_from(d).ok())?;
+
+                let Some(high) = self.pending.take() else {
+                    self.pending = Some(digit);
+                    return None;
+                };
+
+                let input = Input::Poke {
+                    address: self.cursor,
+                    value: (high << 4) | digit,
+                };
+                self.move_by(1);
+                return Some(input);
+            }
+        }
+
+        None
+    }
+
+    fn header(&self) -> String {
+        let editing = self
+            .pending
+            .map_or(String::new(), |high| format!("  EDIT {high:X}-"));
+
+        format!(
+            "CURSOR {:04X}  PC {:04X}  I {:04X}{editing}",
+            self.cursor, self.pc, self.i
+        )
+    }
+}
+
+/// A window showing emulated memory as hex or as sprites, with PC, I and the
+/// bytes written in the last frame highlighted. Typing two hex digits writes
+/// a byte at the cursor.
+#[must_use]
+pub(super) struct MemoryViewer {
+    canvas: Canvas<Window>,
+    editor: Editor,
+}
+
+impl MemoryViewer {
+    /// Opens the window with its top-right corner at `position`.
+    pub(super) fn new(video: &VideoSubsystem, position: (i32, i32)) -> Result<Self> {
+        let width = COLUMNS * CELL_WIDTH * overlay::SCALE;
+        let mut window = video
+            .window("chipper memory", width, ROWS * CELL_HEIGHT * overlay::SCALE)
+            .build()
+            .map_err(Error::new)?;
+        window.set_position(
+            WindowPos::Positioned(position.0.saturating_sub(i32::try_from(width)?)),
+            WindowPos::Positioned(position.1),
+        );
+
+        let mut canvas = window.into_canvas().build().map_err(Error::new)?;
+        canvas
+            .set_logical_size(COLUMNS * CELL_WIDTH, ROWS * CELL_HEIGHT)
+            .map_err(Error::new)?;
+
+        Ok(Self {
+            canvas,
+            editor: Editor::new(),
+        })
+    }
+
+    pub(super) fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub(super) fn key(&mut self, key: Keycode) -> Option<Input> {
+        self.editor.key(key)
+    }
+
+    pub(super) fn draw(&mut self, chip8: &chip8::Chip8) -> Result<()> {
+        self.editor.update(chip8);
+
+        // one set of rectangles per highlight colour, then the cursor
+        let mut rects: [Vec<Rect>; 4] = Default::default();
+        let mut cursor = Vec::new();
+
+        overlay::text_rects(
+            &self.editor.header(),
+            0,
+            0,
+            &mut rects[Mark::Plain as usize],
+        );
+        overlay::text_rects(
+            "ARROWS MOVE  0-F EDIT  HOME PC  END I  TAB VIEW",
+            0,
+            i32::try_from(ROWS)? - 1,
+            &mut rects[Mark::Plain as usize],
+        );
+
+        match self.editor.view {
+            View::Hex => self.hex_rects(&mut rects, &mut cursor),
+            View::Sprites => self.sprite_rects(&mut rects, &mut cursor)?,
+        }
+
+        self.canvas.set_draw_color(overlay::BACKGROUND);
+        self.canvas.clear();
+        self.canvas.set_draw_color(CURSOR);
+        self.canvas.fill_rects(&cursor).map_err(Error::msg)?;
+        for (mark, rects) in [Mark::Pc, Mark::Index, Mark::Written, Mark::Plain]
+            .into_iter()
+            .zip(&rects)
+        {
+            self.canvas.set_draw_color(mark.colour());
+            self.canvas.fill_rects(rects).map_err(Error::msg)?;
+        }
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    fn hex_rects(&self, rects: &mut [Vec<Rect>; 4], cursor: &mut Vec<Rect>) {
+        #[allow(clippy::cast_possible_wrap)]
+        let (width, height) = (CELL_WIDTH as i32, CELL_HEIGHT as i32);
+
+        for address in self.editor.visible() {
+            let offset = address - self.editor.top;
+            let row = FIRST_ROW + i32::from(offset / BYTES_PER_ROW);
+            let column = 5 + 3 * i32::from(offset % BYTES_PER_ROW);
+
+            if offset % BYTES_PER_ROW == 0 {
+                overlay::text_rects(
+                    &format!("{address:04X}"),
+                    0,
+                    row,
+                    &mut rects[Mark::Plain as usize],
+                );
+            }
+            if address == self.editor.cursor {
+                cursor.push(Rect::new(column * width - 1, row * height - 1, 13, 9));
+            }
+
+            let mark = self.editor.mark(address);
+            let value = self.editor.memory[usize::from(address)];
+            overlay::text_rects(
+                &format!("{value:02X}"),
+                column,
+                row,
+                &mut rects[mark as usize],
+            );
+        }
+    }
+
+    fn sprite_rects(&self, rects: &mut [Vec<Rect>; 4], cursor: &mut Vec<Rect>) -> Result<()> {
+        #[allow(clippy::cast_possible_wrap)]
+        let height = CELL_HEIGHT as i32;
+        let band_bytes = BAND_COLUMNS * COLUMN_BYTES;
+
+        for address in self.editor.visible() {
+            let offset = address - self.editor.top;
+            let band = i32::from(offset / band_bytes);
+            let column = i32::from(offset % band_bytes / COLUMN_BYTES);
+            let row = i32::from(offset % COLUMN_BYTES);
+
+            let top = FIRST_ROW * height + band * BAND_HEIGHT;
+            if offset % band_bytes == 0 {
+                let label_row = top / height;
+                overlay::text_rects(
+                    &format!("{address:04X}"),
+                    0,
+                    label_row,
+                    &mut rects[Mark::Plain as usize],
+                );
+            }
+
+            let x = LABEL_WIDTH + column * COLUMN_WIDTH;
+            let y = top + 9 + row * SPRITE_PIXEL;
+            if address == self.editor.cursor {
+                let pixel = u32::try_from(SPRITE_PIXEL)?;
+                cursor.push(Rect::new(x - 1, y, 8 * pixel + 2, pixel));
+            }
+
+            let mark = self.editor.mark(address) as usize;
+            let value = self.editor.memory[usize::from(address)];
+            for bit in 0..8 {
+                if value & (0x80 >> bit) != 0 {
+                    let pixel = u32::try_from(SPRITE_PIXEL)?;
+                    rects[mark].push(Rect::new(x + bit * SPRITE_PIXEL, y, pixel, pixel));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::Keycode;
+
+    use super::Editor;
+    use super::Mark;
+    use super::View;
+    use crate::frontend::Input;
+
+    fn editor() -> Editor {
+        let mut editor = Editor::new();
+        editor.memory = vec![0; 4096];
+        editor.written = vec![false; 4096];
+        editor
+    }
+
+    #[test]
+    fn test_typing_two_digits_pokes_a_byte() {
+        // when
+        let mut editor = editor();
+
+        // then
+        let first = editor.key(Keycode::A);
+        let second = editor.key(Keycode::NUM_7);
+
+        // verify
+        assert_eq!(first, None);
+        assert_eq!(
+            second,
+            Some(Input::Poke {
+                address: 0x200,
+                value: 0xA7
+            })
+        );
+        assert_eq!(editor.cursor, 0x201);
+    }
+
+    #[test]
+    fn test_cursor_scrolls_and_stops_at_the_ends() {
+        // when
+        let mut editor = editor();
+
+        // then
+        editor.key(Keycode::PAGEDOWN);
+        editor.key(Keycode::DOWN);
+        let scrolled = editor.top;
+        editor.key(Keycode::TAB);
+        for _ in 0..400 {
+            editor.key(Keycode::RIGHT);
+        }
+
+        // verify
+        assert_eq!(scrolled, 0x220);
+        assert_eq!(editor.view, View::Sprites);
+        assert_eq!(editor.cursor, 0xFFF);
+        assert!(editor.top <= 0xFF0 && editor.top + editor.page() > 0xFFF);
+    }
+
+    #[test]
+    fn test_cursor_reaches_the_end_of_64k() {
+        // when
+        let mut editor = Editor::new();
+        editor.memory = vec![0; 65_536];
+        editor.written = vec![false; 65_536];
+
+        // then
+        editor.i = 0xFFF0;
+        editor.key(Keycode::END);
+        editor.key(Keycode::PAGEDOWN);
+        for _ in 0..20 {
+            editor.key(Keycode::RIGHT);
+        }
+        let hex: Vec<u16> = editor.visible().collect();
+        editor.key(Keycode::TAB);
+        let sprites: Vec<u16> = editor.visible().collect();
+
+        // verify
+        assert_eq!(editor.cursor, 0xFFFF);
+        assert_eq!(hex.last(), Some(&0xFFFF));
+        assert_eq!(sprites.last(), Some(&0xFFFF));
+        assert_eq!(hex.len(), usize::from(0xFFFF - hex[0]) + 1);
+    }
+
+    #[test]
+    fn test_marks() {
+        // when
+        let mut editor = editor();
+        editor.pc = 0x202;
+        editor.i = 0x300;
+        editor.written[0x400] = true;
+
+        // then
+        let marks: Vec<Mark> = [0x202, 0x203, 0x300, 0x400, 0x401]
+            .into_iter()
+            .map(|a| editor.mark(a))
+            .collect();
+
+        // verify
+        assert_eq!(
+            marks,
+            vec![Mark::Pc, Mark::Pc, Mark::Index, Mark::Written, Mark::Plain]
+        );
+    }
+}
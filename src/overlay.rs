@@ -0,0 +1,297 @@
+use anyhow::Error;
+use anyhow::Result;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::video::WindowPos;
+use sdl2::VideoSubsystem;
+
+use crate::chip8;
+use crate::disassembler;
+
+const COLUMNS: u32 = 36;
+const ROWS: u32 = 28;
+
+// each character cell is a 5x7 glyph with a gap, drawn at twice the size
+pub(super) const CELL_WIDTH: u32 = 6;
+pub(super) const CELL_HEIGHT: u32 = 9;
+pub(super) const SCALE: u32 = 2;
+
+// instructions shown before and after the one at PC
+const BEFORE_PC: u16 = 5;
+const AFTER_PC: u16 = 9;
+
+pub(super) const BACKGROUND: Color = Color::RGB(0x10, 0x10, 0x18);
+pub(super) const TEXT: Color = Color::RGB(0xC8, 0xC8, 0xC8);
+pub(super) const HIGHLIGHT: Color = Color::RGB(0xFF, 0xD2, 0x40);
+
+/// A line of the overlay, and whether it is the instruction at PC.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Line {
+    text: String,
+    highlight: bool,
+}
+
+impl Line {
+    fn new(text: String) -> Self {
+        Self {
+            text,
+            highlight: false,
+        }
+    }
+}
+
+/// A second window showing the registers, stack, timers, keys and the
+/// instructions around PC, redrawn every frame.
+#[must_use]
+pub(super) struct Overlay {
+    canvas: Canvas<Window>,
+}
+
+impl Overlay {
+    /// Opens the window with its top-left corner at `position`.
+    pub(super) fn new(video: &VideoSubsystem, position: (i32, i32)) -> Result<Self> {
+        let mut window = video
+            .window(
+                "chipper debug",
+                COLUMNS * CELL_WIDTH * SCALE,
+                ROWS * CELL_HEIGHT * SCALE,
+            )
+            .build()
+            .map_err(Error::new)?;
+        window.set_position(
+            WindowPos::Positioned(position.0),
+            WindowPos::Positioned(position.1),
+        );
+
+        // not synced to vblank, so that presenting it doesn't cost the main
+        // window a frame
+        let mut canvas = window.into_canvas().build().map_err(Error::new)?;
+        canvas
+            .set_logical_size(COLUMNS * CELL_WIDTH, ROWS * CELL_HEIGHT)
+            .map_err(Error::new)?;
+
+        Ok(Self { canvas })
+    }
+
+    pub(super) fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub(super) fn draw(&mut self, chip8: &chip8::Chip8) -> Result<()> {
+        let mut text = Vec::new();
+        let mut highlight = Vec::new();
+
+        for (row, line) in (0i32..).zip(lines(chip8)) {
+            let rects = if line.highlight {
+                &mut highlight
+            } else {
+                &mut text
+            };
+            text_rects(&line.text, 0, row, rects);
+        }
+
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+        self.canvas.set_draw_color(TEXT);
+        self.canvas.fill_rects(&text).map_err(Error::msg)?;
+        self.canvas.set_draw_color(HIGHLIGHT);
+        self.canvas.fill_rects(&highlight).map_err(Error::msg)?;
+        self.canvas.present();
+
+        Ok(())
+    }
+}
+
+/// The text of the overlay, one entry per row.
+fn lines(chip8: &chip8::Chip8) -> Vec<Line> {
+    let state = chip8.state();
+    let mut lines = Vec::new();
+
+    lines.push(Line::new(format!(
+        "PC {:04X}  I {:04X}  SP {:02X}",
+        state.pc, state.i, state.sp
+    )));
+    lines.push(Line::new(format!(
+        "DT {:02X}  ST {:02X}  HIRES {}",
+        state.delay_timer,
+        state.sound_timer,
+        if chip8.hires_mode() { "ON" } else { "OFF" }
+    )));
+    lines.push(Line::new(String::new()));
+
+    for first in (0..16).step_by(4) {
+        let cells: Vec<String> = (first..first + 4)
+            .map(|x| format!("V{x:X} {:02X}", state.registers[x]))
+            .collect();
+        lines.push(Line::new(cells.join("  ")));
+    }
+    lines.push(Line::new(String::new()));
+
+    // up to 16 return addresses, six to a row
+    let stack: Vec<String> = chip8
+        .stack()
+        .iter()
+        .map(|address| format!("{address:04X}"))
+        .collect();
+    for row in 0..3 {
+        let label = if row == 0 { "STACK" } else { "" };
+        let entries = stack.get(row * 6..stack.len().min(row * 6 + 6));
+        let entries = entries.map(|e| e.join(" ")).unwrap_or_default();
+        lines.push(Line::new(format!("{label:5} {entries}")));
+    }
+
+    let keys: String = (0u8..16)
+        .map(|k| {
+            if chip8.key_pressed(k) {
+                char::from_digit(u32::from(k), 16)
+                    .unwrap_or('?')
+                    .to_ascii_uppercase()
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    lines.push(Line::new(format!("KEYS  {keys}")));
+    lines.push(Line::new(String::new()));
+
+    let memory = chip8.memory();
+    let first = state.pc.saturating_sub(BEFORE_PC * 2);
+    for address in (first..=state.pc.saturating_add(AFTER_PC * 2)).step_by(2) {
+        let at = usize::from(address);
+        let text = match memory.get(at..at + 2) {
+            Some(&[high, low]) => {
+                let opcode = u16::from_be_bytes([high, low]);
+                let marker = if address == state.pc { '>' } else { ' ' };
+                format!(
+                    "{marker} {address:04X} {opcode:04X} {}",
+                    disassembler::disassemble(opcode)
+                )
+            }
+            _ => String::new(),
+        };
+        lines.push(Line {
+            text,
+            highlight: address == state.pc,
+        });
+    }
+
+    lines
+}
+
+/// Adds the rectangles drawing `text` starting at a character cell.
+pub(super) fn text_rects(text: &str, column: i32, row: i32, rects: &mut Vec<Rect>) {
+    // the cell sizes are small constants
+    #[allow(clippy::cast_possible_wrap)]
+    let (width, height) = (CELL_WIDTH as i32, CELL_HEIGHT as i32);
+
+    for (offset, c) in (column..).zip(text.chars()) {
+        glyph_rects(c, offset * width, row * height, rects);
+    }
+}
+
+/// Adds a 1x1 rectangle for each lit pixel of character `c` drawn at (x, y).
+fn glyph_rects(c: char, x: i32, y: i32, rects: &mut Vec<Rect>) {
+    for (dy, bits) in (0i32..).zip(glyph(c)) {
+        for dx in 0i32..5 {
+            if bits & (0x10 >> dx) != 0 {
+                rects.push(Rect::new(x + dx, y + dy, 1, 1));
+            }
+        }
+    }
+}
+
+/// The 5x7 bitmap for a character, one byte per row with the leftmost pixel
+/// in bit 4. Lowercase letters other than `x` are shown as capitals.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        _ if c == 'x' => [0x00, 0x00, 0x11, 0x0A, 0x04, 0x0A, 0x11],
+        ' ' => [0x00; 7],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '[' => [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E],
+        ']' => [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use anyhow::Error;
+
+    use super::lines;
+    use super::ROWS;
+    use crate::chip8::Chip8;
+    use crate::profile;
+    use crate::Target;
+
+    #[test]
+    fn test_lines_show_state_around_pc() -> Result<(), Error> {
+        // when
+        let mut chip8 = Chip8::new(
+            Target::Chip8,
+            *profile::profiles()
+                .get(&Target::Chip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.load_rom(&[0x22, 0x04, 0x00, 0x00, 0x6A, 0x02])?;
+        chip8.emulate_cycle();
+        chip8.press_key(0xB, true);
+
+        // then
+        let lines = lines(&chip8);
+
+        // verify
+        assert_eq!(lines.len(), usize::try_from(ROWS)?);
+        assert_eq!(lines[0].text, "PC 0204  I 0000  SP 01");
+        assert_eq!(lines[8].text, "STACK 0200");
+        assert_eq!(lines[11].text, "KEYS  ...........B....");
+        let current: Vec<_> = lines.iter().filter(|l| l.highlight).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].text, "> 0204 6A02 LD VA, 0x02");
+        Ok(())
+    }
+}
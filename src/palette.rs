@@ -0,0 +1,255 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ValueEnum;
+use sdl2::event::Event;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+
+// cycles through the preset palettes while running
+const CYCLE_SCANCODE: Scancode = Scancode::P;
+
+#[derive(ValueEnum, Debug, Eq, PartialEq, Copy, Clone)]
+pub(crate) enum Preset {
+    Default,
+    OctoClassic,
+    LcdGreen,
+    Amber,
+    Hp48Grey,
+}
+
+impl Preset {
+    const ALL: [Preset; 5] = [
+        Preset::Default,
+        Preset::OctoClassic,
+        Preset::LcdGreen,
+        Preset::Amber,
+        Preset::Hp48Grey,
+    ];
+
+    pub(crate) fn palette(self) -> Palette {
+        let colours = match self {
+            Preset::Default => [Color::RGB(15, 15, 15), Color::RGB(240, 240, 240)],
+            Preset::OctoClassic => [Color::RGB(0x99, 0x66, 0x00), Color::RGB(0xFF, 0xCC, 0x00)],
+            Preset::LcdGreen => [Color::RGB(0xF9, 0xFF, 0xB3), Color::RGB(0x3D, 0x80, 0x26)],
+            Preset::Amber => [Color::RGB(0x1A, 0x0D, 0x00), Color::RGB(0xFF, 0xB0, 0x00)],
+            Preset::Hp48Grey => [Color::RGB(0x9A, 0xA3, 0x8F), Color::RGB(0x2B, 0x2F, 0x27)],
+        };
+
+        Palette { colours }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::from_str(name, true).ok()
+    }
+}
+
+/// The two display colours, indexed by pixel value: background and fill.
+#[must_use]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Palette {
+    colours: [Color; 2],
+}
+
+impl Palette {
+    #[must_use]
+    pub(crate) fn background(self) -> Color {
+        self.colours[0]
+    }
+
+    #[must_use]
+    pub(crate) fn colour(self, pixel: usize) -> Color {
+        self.colours[pixel & 0x1]
+    }
+
+    /// The colours as consecutive RGB byte triples, background first.
+    #[must_use]
+    pub(crate) fn rgb_bytes(self) -> [u8; 6] {
+        let mut bytes = [0u8; 6];
+        for (chunk, colour) in bytes.chunks_exact_mut(3).zip(self.colours) {
+            chunk.copy_from_slice(&[colour.r, colour.g, colour.b]);
+        }
+        bytes
+    }
+
+    /// Builds the starting palette. A `<rom>.palette` file next to the ROM
+    /// (or `file` if given) overrides the preset, and any colours given on
+    /// the command line override both.
+    pub(crate) fn load(
+        preset: Preset,
+        file: Option<&str>,
+        rom_filename: &str,
+        overrides: [Option<Color>; 2],
+    ) -> Result<Self> {
+        let mut palette = preset.palette();
+
+        let per_rom = Path::new(rom_filename).with_extension("palette");
+        if let Some(f) = file {
+            palette = Self::from_file(Path::new(f), palette)?;
+        } else if per_rom.is_file() {
+            palette = Self::from_file(&per_rom, palette)?;
+        }
+
+        for (colour, over) in palette.colours.iter_mut().zip(overrides) {
+            if let Some(c) = over {
+                *colour = c;
+            }
+        }
+
+        Ok(palette)
+    }
+
+    fn from_file(path: &Path, base: Palette) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read palette {}", path.display()))?;
+
+        Self::parse(&contents, base).with_context(|| format!("Invalid palette {}", path.display()))
+    }
+
+    /// Palette files hold `name = value` lines, where name is one of `preset`,
+    /// `background` or `fill`. Blank lines and lines starting with `#` are
+    /// ignored.
+    fn parse(contents: &str, base: Palette) -> Result<Self> {
+        let mut palette = base;
+
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected 'name = value'", num + 1))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            let index = match name {
+                "preset" => {
+                    palette = Preset::from_name(value)
+                        .ok_or_else(|| anyhow!("line {}: unknown preset '{value}'", num + 1))?
+                        .palette();
+                    continue;
+                }
+                "background" => 0,
+                "fill" => 1,
+                _ => return Err(anyhow!("line {}: unknown colour '{name}'", num + 1)),
+            };
+
+            palette.colours[index] =
+                parse_colour(value).with_context(|| format!("line {}", num + 1))?;
+        }
+
+        Ok(palette)
+    }
+}
+
+/// Parses `RRGGBB` or `#RRGGBB` hex colours.
+pub(crate) fn parse_colour(value: &str) -> Result<Color> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("invalid colour '{value}', expected RRGGBB"));
+    }
+
+    let rgb = u32::from_str_radix(hex, 16)?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+
+    Ok(Color::RGB(r, g, b))
+}
+
+/// The active palette plus the presets it can be swapped to while running.
+#[must_use]
+pub(super) struct Selector {
+    choices: Vec<Palette>,
+    index: usize,
+    changed: bool,
+}
+
+impl Selector {
+    pub(super) fn new(initial: Palette) -> Self {
+        let mut choices = vec![initial];
+        choices.extend(
+            Preset::ALL
+                .iter()
+                .map(|p| p.palette())
+                .filter(|p| *p != initial),
+        );
+
+        Self {
+            choices,
+            index: 0,
+            changed: false,
+        }
+    }
+
+    pub(super) fn current(&self) -> Palette {
+        self.choices[self.index]
+    }
+
+    /// Returns true, and clears the flag, if the palette has been swapped
+    /// since the last call.
+    pub(super) fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Swaps to the next palette if the event is the palette hotkey, returning
+    /// true if the event was consumed.
+    pub(super) fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::KeyDown {
+                scancode: Some(CYCLE_SCANCODE),
+                repeat,
+                ..
+            } => {
+                if !repeat {
+                    self.index = (self.index + 1) % self.choices.len();
+                    self.changed = true;
+                }
+                true
+            }
+            Event::KeyUp {
+                scancode: Some(CYCLE_SCANCODE),
+                ..
+            } => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use sdl2::pixels::Color;
+
+    use super::parse_colour;
+    use super::Palette;
+    use super::Preset;
+
+    #[test]
+    fn test_parse_colour() -> Result<(), Error> {
+        assert_eq!(parse_colour("#FFCC00")?, Color::RGB(0xFF, 0xCC, 0x00));
+        assert_eq!(parse_colour("3d8026")?, Color::RGB(0x3D, 0x80, 0x26));
+        assert!(parse_colour("#FFF").is_err());
+        assert!(parse_colour("+12345").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_palette() -> Result<(), Error> {
+        // when
+        let contents = "# amber with a white fill\npreset = amber\nfill = #FFFFFF\n";
+
+        // then
+        let palette = Palette::parse(contents, Preset::Default.palette())?;
+
+        // verify
+        let amber = Preset::Amber.palette();
+        assert_eq!(palette.background(), amber.background());
+        assert_eq!(palette.colour(1), Color::RGB(0xFF, 0xFF, 0xFF));
+        assert!(Palette::parse("blend = #000000", amber).is_err());
+        Ok(())
+    }
+}
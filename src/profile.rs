@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 use std::sync::OnceLock;
 
+use anyhow::Context;
+use anyhow::Result;
+
 use crate::Target;
 
 #[must_use]
@@ -9,8 +12,11 @@ pub(crate) struct Profile {
     screen_width: u8,
     screen_height: u8,
     lores_display_wait: bool,
+    key_wait_beep: bool,
     default_screen_scale: u8,
     memory_capacity: usize,
+    load_address: u16,
+    start_address: u16,
     user_register_count: u8,
 }
 
@@ -30,6 +36,12 @@ impl Profile {
         self.lores_display_wait
     }
 
+    /// True if the buzzer sounds while FX0A waits for a key to be released.
+    #[must_use]
+    pub(crate) fn key_wait_beep(self) -> bool {
+        self.key_wait_beep
+    }
+
     #[must_use]
     pub(crate) fn default_screen_scale(self) -> u8 {
         self.default_screen_scale
@@ -40,60 +52,150 @@ impl Profile {
         self.memory_capacity
     }
 
+    /// Where the ROM is loaded into memory.
+    #[must_use]
+    pub(crate) fn load_address(self) -> u16 {
+        self.load_address
+    }
+
+    /// Where the program starts running, which can be past the start of the
+    /// ROM if it brings part of the interpreter with it.
+    #[must_use]
+    pub(crate) fn start_address(self) -> u16 {
+        self.start_address
+    }
+
     #[must_use]
     pub(crate) fn user_register_count(self) -> u8 {
         self.user_register_count
     }
 }
 
+const PROFILES: [(Target, Profile); 8] = [
+    (
+        Target::Chip8,
+        Profile {
+            screen_width: 64,
+            screen_height: 32,
+            lores_display_wait: true,
+            key_wait_beep: true,
+            default_screen_scale: 12,
+            memory_capacity: 4_096,
+            load_address: 0x200,
+            start_address: 0x200,
+            user_register_count: 0,
+        },
+    ),
+    (
+        Target::HiresChip8,
+        Profile {
+            screen_width: 64,
+            screen_height: 64,
+            lores_display_wait: true,
+            key_wait_beep: true,
+            default_screen_scale: 12,
+            memory_capacity: 4_096,
+            // the ROM starts with the changes to the interpreter
+            load_address: 0x200,
+            start_address: 0x2C0,
+            user_register_count: 0,
+        },
+    ),
+    (
+        Target::Eti660,
+        Profile {
+            screen_width: 64,
+            screen_height: 48,
+            lores_display_wait: false,
+            key_wait_beep: false,
+            default_screen_scale: 12,
+            memory_capacity: 4_096,
+            load_address: 0x600,
+            start_address: 0x600,
+            user_register_count: 0,
+        },
+    ),
+    (
+        Target::Chip48,
+        Profile {
+            screen_width: 64,
+            screen_height: 32,
+            lores_display_wait: false,
+            key_wait_beep: false,
+            default_screen_scale: 12,
+            memory_capacity: 4_096,
+            load_address: 0x200,
+            start_address: 0x200,
+            user_register_count: 0,
+        },
+    ),
+    (
+        Target::SuperChip10,
+        Profile {
+            screen_width: 128,
+            screen_height: 64,
+            lores_display_wait: true,
+            key_wait_beep: false,
+            default_screen_scale: 6,
+            memory_capacity: 4_096,
+            load_address: 0x200,
+            start_address: 0x200,
+            user_register_count: 8,
+        },
+    ),
+    (
+        Target::SuperChipLegacy,
+        Profile {
+            screen_width: 128,
+            screen_height: 64,
+            lores_display_wait: true,
+            key_wait_beep: false,
+            default_screen_scale: 6,
+            memory_capacity: 4_096,
+            load_address: 0x200,
+            start_address: 0x200,
+            user_register_count: 8,
+        },
+    ),
+    (
+        Target::SuperChip,
+        Profile {
+            screen_width: 128,
+            screen_height: 64,
+            lores_display_wait: false,
+            key_wait_beep: false,
+            default_screen_scale: 6,
+            memory_capacity: 4_096,
+            load_address: 0x200,
+            start_address: 0x200,
+            user_register_count: 8,
+        },
+    ),
+    (
+        Target::XoChip,
+        Profile {
+            screen_width: 128,
+            screen_height: 64,
+            lores_display_wait: false,
+            key_wait_beep: false,
+            default_screen_scale: 6,
+            memory_capacity: 65_536,
+            load_address: 0x200,
+            start_address: 0x200,
+            user_register_count: 16,
+        },
+    ),
+];
+
 pub(crate) fn profiles() -> &'static HashMap<Target, Profile> {
     static LOCK: OnceLock<HashMap<Target, Profile>> = OnceLock::new();
-    LOCK.get_or_init(|| {
-        HashMap::from([
-            (
-                Target::Chip8,
-                Profile {
-                    screen_width: 64,
-                    screen_height: 32,
-                    lores_display_wait: true,
-                    default_screen_scale: 12,
-                    memory_capacity: 4_096,
-                    user_register_count: 0,
-                },
-            ),
-            (
-                Target::SuperChipLegacy,
-                Profile {
-                    screen_width: 128,
-                    screen_height: 64,
-                    lores_display_wait: true,
-                    default_screen_scale: 6,
-                    memory_capacity: 4_096,
-                    user_register_count: 8,
-                },
-            ),
-            (
-                Target::SuperChip,
-                Profile {
-                    screen_width: 128,
-                    screen_height: 64,
-                    lores_display_wait: false,
-                    default_screen_scale: 6,
-                    memory_capacity: 4_096,
-                    user_register_count: 8,
-                },
-            ),
-            (
-                Target::XoChip,
-                Profile {
-                    screen_width: 128,
-                    screen_height: 64,
-                    lores_display_wait: false,
-                    default_screen_scale: 6,
-                    memory_capacity: 65_536,
-                    user_register_count: 16,
-                },
-            ),
-        ])
-    })
+    LOCK.get_or_init(|| HashMap::from(PROFILES))
+}
+
+/// The profile for the given target.
+pub(crate) fn for_target(target: Target) -> Result<Profile> {
+    profiles()
+        .get(&target)
+        .copied()
+        .context("Unknown target architecture")
 }
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::disassembler;
+use crate::emulator::Observer;
+use crate::emulator::Step;
+use crate::Action;
+
+// how many of the busiest addresses and frames are reported
+const TOP: usize = 20;
+
+/// What to do with the profile once the run ends.
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    /// Print a report of the hotspots to stderr
+    pub(crate) report: bool,
+    /// Write the instructions run in each call stack to this file, in the
+    /// folded format read by flamegraph tools
+    pub(crate) folded: Option<PathBuf>,
+    /// Report frames drawing more sprites than this
+    pub(crate) draw_limit: u32,
+}
+
+/// A subroutine in the call tree, reached through the path of calls from the
+/// main program (node 0).
+#[derive(Debug)]
+struct Node {
+    address: u16,
+    parent: usize,
+    calls: u64,
+    // instructions run in this subroutine itself, not in those it called
+    instructions: u64,
+    children: HashMap<u16, usize>,
+}
+
+/// Counts the instructions run at each address, in each opcode class and in
+/// each subroutine, and the sprites drawn in each frame.
+#[must_use]
+pub(crate) struct Profiler {
+    options: Options,
+    addresses: HashMap<u16, (u64, u16)>,
+    classes: [u64; 16],
+    nodes: Vec<Node>,
+    current: usize,
+    frame: u32,
+    draws: u32,
+    // (frame, sprites drawn) for the frames over the limit
+    busy_frames: Vec<(u32, u32)>,
+    total: u64,
+}
+
+impl Profiler {
+    pub(crate) fn new(options: &Options) -> Self {
+        Self {
+            options: options.clone(),
+            addresses: HashMap::new(),
+            classes: [0; 16],
+            nodes: vec![Node {
+                address: 0,
+                parent: 0,
+                calls: 1,
+                instructions: 0,
+                children: HashMap::new(),
+            }],
+            current: 0,
+            frame: 0,
+            draws: 0,
+            busy_frames: Vec::new(),
+            total: 0,
+        }
+    }
+
+    fn end_frame(&mut self) {
+        if self.draws > self.options.draw_limit {
+            self.busy_frames.push((self.frame, self.draws));
+        }
+        self.draws = 0;
+    }
+
+    fn enter(&mut self, address: u16) {
+        let next = self.nodes.len();
+        let child = *self.nodes[self.current]
+            .children
+            .entry(address)
+            .or_insert(next);
+
+        if child == next {
+            self.nodes.push(Node {
+                address,
+                parent: self.current,
+                calls: 0,
+                instructions: 0,
+                children: HashMap::new(),
+            });
+        }
+
+        self.nodes[child].calls += 1;
+        self.current = child;
+    }
+
+    fn name(address: u16) -> String {
+        format!("sub_{address:04X}")
+    }
+
+    /// The subroutine nodes on the path to `node`, outermost first.
+    fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        while node != 0 {
+            path.push(node);
+            node = self.nodes[node].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Lines of `main;sub_0240;sub_0300 count`, one for each call stack.
+    fn folded(&self) -> Vec<String> {
+        let mut lines: Vec<String> = (0..self.nodes.len())
+            .filter(|n| self.nodes[*n].instructions > 0)
+            .map(|n| {
+                let mut line = "main".to_string();
+                for p in self.path(n) {
+                    let _ = write!(line, ";{}", Self::name(self.nodes[p].address));
+                }
+                let _ = write!(line, " {}", self.nodes[n].instructions);
+                line
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    /// (address, calls, instructions including those in subroutines it
+    /// called, instructions in the subroutine itself) per subroutine, busiest
+    /// first. Recursive calls are counted once.
+    fn subroutines(&self) -> Vec<(u16, u64, u64, u64)> {
+        let mut totals: HashMap<u16, (u64, u64, u64)> = HashMap::new();
+
+        for n in 1..self.nodes.len() {
+            let path = self.path(n);
+            let node = &self.nodes[n];
+
+            let entry = totals.entry(node.address).or_default();
+            entry.0 += node.calls;
+            entry.2 += node.instructions;
+
+            let mut seen = Vec::new();
+            for p in path {
+                let address = self.nodes[p].address;
+                if !seen.contains(&address) {
+                    seen.push(address);
+                    totals.entry(address).or_default().1 += node.instructions;
+                }
+            }
+        }
+
+        let mut subroutines: Vec<_> = totals
+            .into_iter()
+            .map(|(address, (calls, total, own))| (address, calls, total, own))
+            .collect();
+        subroutines.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        subroutines
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let percent = count as f64 * 100.0 / self.total.max(1) as f64;
+        percent
+    }
+
+    fn report(&self) -> String {
+        let mut report = format!(
+            "profile: {} instructions over {} frames\n",
+            self.total,
+            self.frame + 1
+        );
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+        let _ = writeln!(
+            report,
+            "\nhottest addresses\n  {:4}  {:>10} {:>6}  instruction",
+            "pc", "count", "%"
+        );
+        for (pc, (count, opcode)) in addresses.into_iter().take(TOP) {
+            let _ = writeln!(
+                report,
+                "  {pc:04X}  {count:>10} {:>6.2}  {:04X} {}",
+                self.percent(*count),
+                opcode,
+                disassembler::disassemble(*opcode)
+            );
+        }
+
+        let mut classes: Vec<_> = (0u8..).zip(self.classes).filter(|c| c.1 > 0).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(
+            report,
+            "\nopcode classes\n  {:5} {:>10} {:>6}",
+            "class", "count", "%"
+        );
+        for (class, count) in classes {
+            let _ = writeln!(
+                report,
+                "  {class:X}xxx  {count:>10} {:>6.2}",
+                self.percent(count)
+            );
+        }
+
+        let subroutines = self.subroutines();
+        if !subroutines.is_empty() {
+            let _ = writeln!(
+                report,
+                "\nsubroutines\n  {:4}  {:>8} {:>10} {:>6} {:>10}",
+                "addr", "calls", "total", "%", "self"
+            );
+            for (address, calls, total, own) in subroutines {
+                let _ = writeln!(
+                    report,
+                    "  {address:04X}  {calls:>8} {total:>10} {:>6.2} {own:>10}",
+                    self.percent(total)
+                );
+            }
+        }
+
+        let _ = writeln!(
+            report,
+            "\n{} frames drew more than {} sprites",
+            self.busy_frames.len(),
+            self.options.draw_limit
+        );
+        let mut busy = self.busy_frames.clone();
+        busy.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (frame, draws) in busy.into_iter().take(TOP) {
+            let _ = writeln!(report, "  frame {frame:>6}  {draws:>4} sprites");
+        }
+
+        report
+    }
+}
+
+impl Observer for Profiler {
+    fn instruction(&mut self, step: &Step) -> Result<Option<Action>> {
+        if step.frame != self.frame {
+            self.end_frame();
+            self.frame = step.frame;
+        }
+
+        let class = usize::from(step.opcode >> 12);
+        self.total += 1;
+        self.classes[class] += 1;
+        if class == 0xD {
+            self.draws += 1;
+        }
+
+        let entry = self.addresses.entry(step.before.pc).or_default();
+        entry.0 += 1;
+        entry.1 = step.opcode;
+
+        // the call or return counts towards the subroutine it was run in
+        self.nodes[self.current].instructions += 1;
+        if step.after.sp > step.before.sp {
+            self.enter(step.after.pc);
+        } else if step.after.sp < step.before.sp {
+            self.current = self.nodes[self.current].parent;
+        }
+
+        Ok(None)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.end_frame();
+
+        if let Some(path) = &self.options.folded {
+            let file = File::create(path)
+                .with_context(|| format!("Unable to create {}", path.display()))?;
+            let mut out = BufWriter::new(file);
+            for line in self.folded() {
+                writeln!(out, "{line}")?;
+            }
+            out.flush()?;
+        }
+
+        if self.options.report {
+            eprint!("{}", self.report());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use super::Options;
+    use super::Profiler;
+    use crate::chip8;
+    use crate::emulator::Observer;
+    use crate::emulator::Step;
+
+    fn step(frame: u32, pc: u16, opcode: u16, sp: u8, next: (u16, u8)) -> Step {
+        let state = chip8::State {
+            registers: [0; 16],
+            i: 0,
+            pc,
+            sp,
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+
+        Step {
+            cycle: 0,
+            frame,
+            opcode,
+            before: state,
+            after: chip8::State {
+                pc: next.0,
+                sp: next.1,
+                ..state
+            },
+        }
+    }
+
+    #[test]
+    fn test_subroutines_and_draws() -> Result<(), Error> {
+        // when
+        let mut profiler = Profiler::new(&Options {
+            report: false,
+            folded: None,
+            draw_limit: 1,
+        });
+        let steps = [
+            step(0, 0x200, 0x2300, 0, (0x300, 1)),
+            step(0, 0x300, 0xD125, 1, (0x302, 1)),
+            step(0, 0x302, 0xD125, 1, (0x304, 1)),
+            step(0, 0x304, 0x00EE, 1, (0x202, 0)),
+            step(1, 0x202, 0x2300, 0, (0x300, 1)),
+            step(1, 0x300, 0xD125, 1, (0x302, 1)),
+        ];
+
+        // then
+        for s in &steps {
+            profiler.instruction(s)?;
+        }
+        profiler.end_frame();
+
+        // verify
+        assert_eq!(profiler.total, 6);
+        assert_eq!(profiler.classes[0xD], 3);
+        assert_eq!(profiler.addresses[&0x300], (2, 0xD125));
+        assert_eq!(profiler.subroutines(), vec![(0x300, 2, 4, 4)]);
+        assert_eq!(profiler.folded(), vec!["main 2", "main;sub_0300 4"]);
+        assert_eq!(profiler.busy_frames, vec![(0, 2)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+
+use crate::palette;
+use crate::profile;
+use crate::screenshot;
+
+// GIF frame delays are in hundredths of a second
+const GIF_TICKS_PER_SECOND: u64 = 100;
+const FRAMES_PER_SECOND: u64 = 60;
+
+// viewers play anything shorter than 2 ticks far slower, commonly at 10 ticks
+const MIN_GIF_DELAY: u64 = 2;
+
+enum Sink {
+    Gif(gif::Encoder<BufWriter<File>>),
+    PngSequence(PathBuf),
+}
+
+/// A frame that has been presented but not yet written, as it may be
+/// repeated by the frames that follow.
+struct Pending {
+    pixels: Vec<u8>,
+    palette: palette::Palette,
+    first_frame: u64,
+}
+
+/// Records every presented frame to an animated GIF, or a numbered PNG
+/// sequence if the output filename doesn't end in `.gif`.
+///
+/// Runs of identical frames are written once: a GIF frame's delay covers the
+/// whole run, and PNG files are numbered by the frame they first appeared on.
+/// A GIF frame that would last less than 2 hundredths of a second is left out.
+#[must_use]
+pub(crate) struct Recorder {
+    sink: Sink,
+    profile: profile::Profile,
+    scale: u8,
+    width: u16,
+    height: u16,
+    frame: u64,
+    // the GIF ticks covered by the frames written so far
+    written_ticks: u64,
+    pending: Option<Pending>,
+}
+
+impl Recorder {
+    pub(crate) fn new(
+        path: &Path,
+        profile: profile::Profile,
+        scale: u8,
+        palette: palette::Palette,
+    ) -> Result<Self> {
+        let width = u16::from(profile.screen_width()) * u16::from(scale);
+        let height = u16::from(profile.screen_height()) * u16::from(scale);
+
+        let sink = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+        {
+            let file = File::create(path)
+                .with_context(|| format!("Unable to create recording {}", path.display()))?;
+            let mut encoder =
+                gif::Encoder::new(BufWriter::new(file), width, height, &palette.rgb_bytes())?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            Sink::Gif(encoder)
+        } else {
+            Sink::PngSequence(path.to_path_buf())
+        };
+
+        Ok(Self {
+            sink,
+            profile,
+            scale,
+            width,
+            height,
+            frame: 0,
+            written_ticks: 0,
+            pending: None,
+        })
+    }
+
+    /// Adds the currently presented display. Must be called once per 60 Hz
+    /// frame, whether or not the display has changed.
+    pub(crate) fn add_frame(
+        &mut self,
+        gfx: &BitVec,
+        res_scale: u8,
+        palette: palette::Palette,
+    ) -> Result<()> {
+        let pixels = screenshot::indexed(gfx, self.profile, res_scale, self.scale);
+
+        let repeated = self
+            .pending
+            .as_ref()
+            .is_some_and(|p| p.pixels == pixels && p.palette == palette);
+
+        if !repeated {
+            if let Some(previous) = self.pending.take() {
+                self.write(previous, false)?;
+            }
+            self.pending = Some(Pending {
+                pixels,
+                palette,
+                first_frame: self.frame,
+            });
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Writes out the final frame and closes the recording.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        if let Some(last) = self.pending.take() {
+            self.write(last, true)?;
+        }
+
+        if let Sink::Gif(encoder) = self.sink {
+            encoder.into_inner()?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, pending: Pending, last: bool) -> Result<()> {
+        match &mut self.sink {
+            Sink::Gif(encoder) => {
+                // round each frame boundary to the nearest GIF tick, so that
+                // rounding errors don't accumulate over the recording
+                let ticks = Self::gif_ticks(self.frame);
+                let delay = ticks.saturating_sub(self.written_ticks);
+                if delay < MIN_GIF_DELAY && !last {
+                    // too short to be shown properly, so it's dropped and the
+                    // next frame written takes its time
+                    return Ok(());
+                }
+                let delay = delay.max(MIN_GIF_DELAY);
+                self.written_ticks += delay;
+
+                let mut frame =
+                    gif::Frame::from_indexed_pixels(self.width, self.height, pending.pixels, None);
+                frame.delay = u16::try_from(delay).unwrap_or(u16::MAX);
+                frame.palette = Some(pending.palette.rgb_bytes().to_vec());
+                encoder.write_frame(&frame)?;
+            }
+            Sink::PngSequence(path) => {
+                let image = screenshot::Image::from_indexed(
+                    u32::from(self.width),
+                    u32::from(self.height),
+                    &pending.pixels,
+                    pending.palette,
+                );
+                image.write_png(&Self::sequence_filename(path, pending.first_frame))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn gif_ticks(frame: u64) -> u64 {
+        (frame * GIF_TICKS_PER_SECOND + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+    }
+
+    fn sequence_filename(path: &Path, frame: u64) -> PathBuf {
+        let stem = path
+            .file_stem()
+            .map_or_else(|| "frame".into(), |s| s.to_string_lossy());
+
+        path.with_file_name(format!("{stem}-{frame:06}.png"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use anyhow::Error;
+    use bitvec::prelude::BitVec;
+
+    use super::Recorder;
+    use crate::palette;
+    use crate::profile;
+    use crate::Target;
+
+    #[test]
+    fn test_gif_delays_are_never_too_short() -> Result<(), Error> {
+        // when
+        let path = env::temp_dir().join(format!("chipper-{}-delays.gif", std::process::id()));
+        let palette = palette::Preset::Default.palette();
+        let mut recorder = Recorder::new(&path, profile::for_target(Target::Chip8)?, 1, palette)?;
+        let mut gfx = BitVec::repeat(false, 64 * 32);
+
+        // then
+        // a display that changes every frame for a second
+        for frame in 0..60 {
+            gfx.set(0, frame % 2 == 0);
+            recorder.add_frame(&gfx, 1, palette)?;
+        }
+        recorder.finish()?;
+
+        let mut decoder = gif::DecodeOptions::new().read_info(File::open(&path)?)?;
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame()? {
+            delays.push(frame.delay);
+        }
+        fs::remove_file(&path)?;
+
+        // verify
+        assert!(delays.iter().all(|&delay| delay >= 2), "{delays:?}");
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gif_ticks_do_not_drift() {
+        // 60 frames is exactly one second, whatever the rounding in between
+        assert_eq!(Recorder::gif_ticks(60), 100);
+        assert_eq!(Recorder::gif_ticks(3600), 6000);
+        assert_eq!(Recorder::gif_ticks(1), 2);
+        assert_eq!(Recorder::gif_ticks(2) - Recorder::gif_ticks(1), 1);
+    }
+
+    #[test]
+    fn test_sequence_filename() {
+        assert_eq!(
+            Recorder::sequence_filename(Path::new("out/tetris.png"), 42),
+            PathBuf::from("out/tetris-000042.png")
+        );
+    }
+}
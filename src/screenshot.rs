@@ -0,0 +1,218 @@
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+
+use crate::palette;
+use crate::profile;
+
+/// An RGB image of the emulated display.
+#[must_use]
+pub(crate) struct Image {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+}
+
+impl Image {
+    /// Renders the display as shown on screen, `scale` pixels per display pixel.
+    /// In lores modes the framebuffer only uses its top-left quarter, which is
+    /// stretched over the whole image by `res_scale`.
+    pub(crate) fn render(
+        gfx: &BitVec,
+        profile: profile::Profile,
+        res_scale: u8,
+        scale: u8,
+        palette: palette::Palette,
+    ) -> Self {
+        Self::from_indexed(
+            u32::from(profile.screen_width()) * u32::from(scale),
+            u32::from(profile.screen_height()) * u32::from(scale),
+            &indexed(gfx, profile, res_scale, scale),
+            palette,
+        )
+    }
+
+    pub(crate) fn from_indexed(
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        palette: palette::Palette,
+    ) -> Self {
+        let rgb = pixels
+            .iter()
+            .flat_map(|index| {
+                let colour = palette.colour(usize::from(*index));
+                [colour.r, colour.g, colour.b]
+            })
+            .collect();
+
+        Self { width, height, rgb }
+    }
+
+    pub(crate) fn write_png(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Unable to create screenshot {}", path.display()))?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgb)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Renders the display as palette indices, one byte per pixel, scaled as for
+/// [`Image::render`].
+pub(crate) fn indexed(
+    gfx: &BitVec,
+    profile: profile::Profile,
+    res_scale: u8,
+    scale: u8,
+) -> Vec<u8> {
+    let sw = usize::from(profile.screen_width());
+    let sh = usize::from(profile.screen_height());
+    let pixel_size = usize::from(res_scale) * usize::from(scale);
+    let (width, height) = (sw * usize::from(scale), sh * usize::from(scale));
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(u8::from(gfx[(y / pixel_size) * sw + (x / pixel_size)]));
+        }
+    }
+
+    pixels
+}
+
+/// Writes the display at scale 1 as a plain (ASCII) PBM bitmap.
+pub(crate) fn write_pbm(
+    path: &Path,
+    gfx: &BitVec,
+    profile: profile::Profile,
+    res_scale: u8,
+) -> Result<()> {
+    let width = usize::from(profile.screen_width());
+    let height = usize::from(profile.screen_height());
+
+    let mut contents = format!("P1\n{width} {height}\n");
+    for row in indexed(gfx, profile, res_scale, 1).chunks(width) {
+        let line: Vec<&str> = row
+            .iter()
+            .map(|p| if *p == 0 { "0" } else { "1" })
+            .collect();
+        contents.push_str(&line.join(" "));
+        contents.push('\n');
+    }
+
+    fs::write(path, contents).with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Renders the display at scale 1 as text, `#` for set pixels and `.` for
+/// unset ones.
+pub(crate) fn ascii(gfx: &BitVec, profile: profile::Profile, res_scale: u8) -> String {
+    let width = usize::from(profile.screen_width());
+
+    indexed(gfx, profile, res_scale, 1)
+        .chunks(width)
+        .map(|row| {
+            let mut line: String = row
+                .iter()
+                .map(|p| if *p == 0 { '.' } else { '#' })
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+/// An image or recording file, and how many pixels per display pixel to write.
+#[must_use]
+#[derive(Debug, Clone)]
+pub(crate) struct Output {
+    pub(crate) path: PathBuf,
+    pub(crate) scale: u8,
+}
+
+/// A fresh filename for a hotkey screenshot or recording of the given ROM, in the
+/// current directory.
+pub(crate) fn hotkey_filename(rom_filename: &str, extension: &str) -> PathBuf {
+    let stem = Path::new(rom_filename)
+        .file_stem()
+        .map_or_else(|| "chipper".into(), |s| s.to_string_lossy());
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+
+    PathBuf::from(format!("{stem}-{millis}.{extension}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use anyhow::Error;
+    use bitvec::prelude::BitVec;
+
+    use super::ascii;
+    use super::Image;
+    use crate::palette;
+    use crate::profile;
+    use crate::Target;
+
+    #[test]
+    fn test_render_lores_scaled() -> Result<(), Error> {
+        // when
+        let profile = *profile::profiles()
+            .get(&Target::SuperChip)
+            .ok_or(anyhow!("Unknown profile"))?;
+        let mut gfx = BitVec::repeat(false, 128 * 64);
+        gfx.set(1, true);
+        let palette = palette::Preset::Default.palette();
+
+        // then
+        let image = Image::render(&gfx, profile, 2, 3, palette);
+
+        // verify
+        assert_eq!(image.width, 128 * 3);
+        assert_eq!(image.height, 64 * 3);
+        let fill = palette.colour(1);
+        let background = palette.colour(0);
+        // lores pixel 1 covers image columns 6..12 at scale 3
+        assert_eq!(image.rgb[5 * 3], background.r);
+        assert_eq!(image.rgb[6 * 3], fill.r);
+        assert_eq!(image.rgb[11 * 3], fill.r);
+        assert_eq!(image.rgb[12 * 3], background.r);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ascii() -> Result<(), Error> {
+        // when
+        let profile = *profile::profiles()
+            .get(&Target::Chip8)
+            .ok_or(anyhow!("Unknown profile"))?;
+        let mut gfx = BitVec::repeat(false, 64 * 32);
+        gfx.set(64 + 2, true);
+
+        // then
+        let text = ascii(&gfx, profile, 1);
+
+        // verify
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(lines[0], ".".repeat(64));
+        assert_eq!(lines[1], format!("..#{}", ".".repeat(61)));
+        Ok(())
+    }
+}
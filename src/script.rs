@@ -0,0 +1,378 @@
+//! Rhai scripts attached to a run, e.g. to play a ROM automatically and check
+//! what it does. A script registers callbacks when it is loaded:
+//!
+//! ```text
+//! on_frame(|frame| { if frame == 60 { press(5) } else if frame == 65 { release(5) } });
+//! on_pc(0x2A0, |pc| { if reg(3) == 0 { throw "out of lives" } });
+//! on_write(0x3A0, |address, value| { print(`score ${value}`) });
+//! ```
+//!
+//! `on_pc` fires once the instruction at the address has run, and `on_write`
+//! when an instruction stores to it. Inside a callback, `reg(x)`, `set_reg(x, value)`,
+//! `index()`, `pc()`, `frame()`, `peek(address)` and `poke(address, value)`
+//! read and write the machine, `press(key)` and `release(key)` work the keypad,
+//! `screenshot(path)` saves a PNG of the display and `stop()` ends the run.
+//! An error thrown by the script also ends the run, with that error.
+
+use std::cell::RefCell;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::EvalAltResult;
+use rhai::FnPtr;
+use rhai::AST;
+
+use crate::chip8;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Something a callback asked the emulator to do.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) enum Command {
+    SetRegister { x: u8, value: u8 },
+    Poke { address: u16, value: u8 },
+    Key { key: u8, pressed: bool },
+    Screenshot { path: PathBuf },
+    Stop,
+}
+
+/// The callbacks registered by the script.
+#[derive(Debug, Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    pc: Vec<(u16, FnPtr)>,
+    write: Vec<(u16, FnPtr)>,
+}
+
+/// A copy of the machine for callbacks to read, and the commands they give.
+#[derive(Debug)]
+struct Host {
+    state: chip8::State,
+    memory: Vec<u8>,
+    frame: u32,
+    commands: Vec<Command>,
+}
+
+impl Host {
+    fn sync(&mut self, chip8: &chip8::Chip8, frame: u32) {
+        self.state = chip8.state();
+        self.memory.clear();
+        self.memory.extend_from_slice(chip8.memory());
+        self.frame = frame;
+    }
+}
+
+fn address(value: i64) -> ScriptResult<u16> {
+    u16::try_from(value).map_err(|_| format!("invalid address {value:#X}").into())
+}
+
+fn byte(value: i64) -> ScriptResult<u8> {
+    u8::try_from(value).map_err(|_| format!("invalid byte {value}").into())
+}
+
+fn nibble(value: i64, what: &str) -> ScriptResult<u8> {
+    u8::try_from(value)
+        .ok()
+        .filter(|v| *v <= 0xF)
+        .ok_or_else(|| format!("invalid {what} {value}").into())
+}
+
+/// A loaded script and its callbacks.
+#[must_use]
+pub(super) struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    hooks: Rc<RefCell<Hooks>>,
+    host: Rc<RefCell<Host>>,
+}
+
+impl Script {
+    pub(super) fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read script {}", path.display()))?;
+
+        Self::compile(&path.display().to_string(), &source)
+    }
+
+    fn compile(name: &str, source: &str) -> Result<Self> {
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let host = Rc::new(RefCell::new(Host {
+            state: chip8::State {
+                registers: [0; 16],
+                i: 0,
+                pc: 0x200,
+                sp: 0,
+                delay_timer: 0,
+                sound_timer: 0,
+            },
+            memory: Vec::new(),
+            frame: 0,
+            commands: Vec::new(),
+        }));
+
+        let mut engine = Engine::new();
+        Self::register_hooks(&mut engine, &hooks);
+        Self::register_machine(&mut engine, &host);
+
+        let ast = engine.compile(source).map_err(|e| anyhow!("{name}: {e}"))?;
+        engine.run_ast(&ast).map_err(|e| anyhow!("{name}: {e}"))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            engine,
+            ast,
+            hooks,
+            host,
+        })
+    }
+
+    fn register_hooks(engine: &mut Engine, hooks: &Rc<RefCell<Hooks>>) {
+        let h = Rc::clone(hooks);
+        engine.register_fn("on_frame", move |f: FnPtr| h.borrow_mut().frame.push(f));
+
+        let h = Rc::clone(hooks);
+        engine.register_fn("on_pc", move |pc: i64, f: FnPtr| -> ScriptResult<()> {
+            h.borrow_mut().pc.push((address(pc)?, f));
+            Ok(())
+        });
+
+        let h = Rc::clone(hooks);
+        engine.register_fn("on_write", move |a: i64, f: FnPtr| -> ScriptResult<()> {
+            h.borrow_mut().write.push((address(a)?, f));
+            Ok(())
+        });
+    }
+
+    fn register_machine(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+        let h = Rc::clone(host);
+        engine.register_fn("reg", move |x: i64| -> ScriptResult<i64> {
+            let x = nibble(x, "register")?;
+            Ok(i64::from(h.borrow().state.registers[usize::from(x)]))
+        });
+
+        let h = Rc::clone(host);
+        engine.register_fn("set_reg", move |x: i64, value: i64| -> ScriptResult<()> {
+            let (x, value) = (nibble(x, "register")?, byte(value)?);
+            let mut host = h.borrow_mut();
+            host.state.registers[usize::from(x)] = value;
+            host.commands.push(Command::SetRegister { x, value });
+            Ok(())
+        });
+
+        let h = Rc::clone(host);
+        engine.register_fn("index", move || i64::from(h.borrow().state.i));
+
+        let h = Rc::clone(host);
+        engine.register_fn("pc", move || i64::from(h.borrow().state.pc));
+
+        let h = Rc::clone(host);
+        engine.register_fn("frame", move || i64::from(h.borrow().frame));
+
+        let h = Rc::clone(host);
+        engine.register_fn("peek", move |a: i64| -> ScriptResult<i64> {
+            let host = h.borrow();
+            host.memory
+                .get(usize::from(address(a)?))
+                .map(|v| i64::from(*v))
+                .ok_or_else(|| format!("invalid address {a:#X}").into())
+        });
+
+        let h = Rc::clone(host);
+        engine.register_fn("poke", move |a: i64, value: i64| -> ScriptResult<()> {
+            let (address, value) = (address(a)?, byte(value)?);
+            let mut host = h.borrow_mut();
+            let Some(byte) = host.memory.get_mut(usize::from(address)) else {
+                return Err(format!("invalid address {a:#X}").into());
+            };
+            *byte = value;
+            host.commands.push(Command::Poke { address, value });
+            Ok(())
+        });
+
+        for (name, pressed) in [("press", true), ("release", false)] {
+            let h = Rc::clone(host);
+            engine.register_fn(name, move |key: i64| -> ScriptResult<()> {
+                let key = nibble(key, "key")?;
+                h.borrow_mut().commands.push(Command::Key { key, pressed });
+                Ok(())
+            });
+        }
+
+        let h = Rc::clone(host);
+        engine.register_fn("screenshot", move |path: &str| {
+            h.borrow_mut().commands.push(Command::Screenshot {
+                path: PathBuf::from(path),
+            });
+        });
+
+        let h = Rc::clone(host);
+        engine.register_fn("stop", move || h.borrow_mut().commands.push(Command::Stop));
+    }
+
+    /// True if the script needs to see each instruction.
+    pub(super) fn watches_instructions(&self) -> bool {
+        let hooks = self.hooks.borrow();
+        !hooks.pc.is_empty() || !hooks.write.is_empty()
+    }
+
+    fn call(&self, f: &FnPtr, args: impl rhai::FuncArgs) -> Result<()> {
+        f.call::<Dynamic>(&self.engine, &self.ast, args)
+            .map(|_| ())
+            .map_err(|e| anyhow!("{}: {e}", self.name))
+    }
+
+    /// Runs the callbacks for the instruction at `pc` that has just run and
+    /// stored to `written`, if anything, returning the commands they gave.
+    pub(super) fn instruction(
+        &mut self,
+        chip8: &chip8::Chip8,
+        frame: u32,
+        pc: u16,
+        written: Option<&RangeInclusive<u16>>,
+    ) -> Result<Vec<Command>> {
+        let (pc_calls, write_calls): (Vec<FnPtr>, Vec<(FnPtr, u16)>) = {
+            let hooks = self.hooks.borrow();
+            let pc_calls = hooks
+                .pc
+                .iter()
+                .filter(|(at, _)| *at == pc)
+                .map(|(_, f)| f.clone())
+                .collect();
+            let write_calls = hooks
+                .write
+                .iter()
+                .filter(|(at, _)| written.is_some_and(|w| w.contains(at)))
+                .map(|(at, f)| (f.clone(), *at))
+                .collect();
+            (pc_calls, write_calls)
+        };
+
+        if pc_calls.is_empty() && write_calls.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.host.borrow_mut().sync(chip8, frame);
+        for f in pc_calls {
+            self.call(&f, (i64::from(pc),))?;
+        }
+        for (f, address) in write_calls {
+            let value = chip8.memory().get(usize::from(address)).copied();
+            self.call(&f, (i64::from(address), i64::from(value.unwrap_or(0))))?;
+        }
+
+        Ok(std::mem::take(&mut self.host.borrow_mut().commands))
+    }
+
+    /// Runs the frame callbacks, returning the commands they gave.
+    pub(super) fn frame(&mut self, chip8: &chip8::Chip8, frame: u32) -> Result<Vec<Command>> {
+        let calls = self.hooks.borrow().frame.clone();
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.host.borrow_mut().sync(chip8, frame);
+        for f in calls {
+            self.call(&f, (i64::from(frame),))?;
+        }
+
+        Ok(std::mem::take(&mut self.host.borrow_mut().commands))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use anyhow::Error;
+
+    use super::Command;
+    use super::Script;
+    use crate::chip8::Chip8;
+    use crate::profile;
+    use crate::Target;
+
+    fn chip8(rom: &[u8]) -> Result<Chip8, Error> {
+        let mut chip8 = Chip8::new(
+            Target::Chip8,
+            *profile::profiles()
+                .get(&Target::Chip8)
+                .ok_or(anyhow!("Unknown profile"))?,
+            Box::new(rand::rng()),
+        );
+        chip8.load_rom(rom)?;
+        Ok(chip8)
+    }
+
+    #[test]
+    fn test_pc_and_write_hooks() -> Result<(), Error> {
+        // when
+        // 607B: V0 = 123, A300: I = 300, F033: store V0 as BCD, 1206: loop forever
+        let mut chip8 = chip8(&[0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x06])?;
+        let mut script = Script::compile(
+            "test.rhai",
+            "on_pc(0x200, |pc| set_reg(2, reg(0)));
+             on_pc(0x204, |pc| set_reg(1, reg(0) + 1));
+             on_write(0x301, |address, value| { poke(0x310, value); if value == 2 { stop() } });",
+        )?;
+
+        // then
+        let mut commands = Vec::new();
+        for _ in 0..4 {
+            let pc = chip8.program_counter();
+            let written = chip8.next_writes();
+            chip8.emulate_cycle();
+            commands.extend(script.instruction(&chip8, 0, pc, written.as_ref())?);
+        }
+
+        // verify
+        assert!(script.watches_instructions());
+        assert_eq!(
+            commands,
+            vec![
+                Command::SetRegister { x: 2, value: 123 },
+                Command::SetRegister { x: 1, value: 124 },
+                Command::Poke {
+                    address: 0x310,
+                    value: 2
+                },
+                Command::Stop,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_hook_errors_end_the_run() -> Result<(), Error> {
+        // when
+        let chip8 = chip8(&[0x12, 0x00])?;
+        let mut script = Script::compile(
+            "test.rhai",
+            r#"on_frame(|frame| { if frame == 2 { throw "boom" } press(frame) });"#,
+        )?;
+
+        // then
+        let first = script.frame(&chip8, 1)?;
+        let second = script.frame(&chip8, 2);
+
+        // verify
+        assert!(!script.watches_instructions());
+        assert_eq!(
+            first,
+            vec![Command::Key {
+                key: 1,
+                pressed: true
+            }]
+        );
+        assert!(second.is_err_and(|e| e.to_string().contains("boom")));
+        assert!(Script::compile("test.rhai", "on_pc(-1, |pc| stop());").is_err());
+        Ok(())
+    }
+}
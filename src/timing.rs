@@ -0,0 +1,289 @@
+//! How long instructions take. With the fixed timing every instruction costs
+//! the same, and `CYCLES_PER_FRAME` of them run in each 60 Hz frame. The VIP
+//! timing instead charges the machine cycles each instruction took in the
+//! COSMAC VIP's interpreter, against the cycles left in a frame once the
+//! display has had its share, so that programs run at the original speed.
+
+use clap::ValueEnum;
+
+use crate::emulator::Step;
+
+/// The instructions run in a frame with the fixed timing.
+const CYCLES_PER_FRAME: u8 = 20;
+
+// the VIP's 1802 runs at 1.7609 MHz, with 8 clocks to a machine cycle
+const MACHINE_CYCLES_PER_FRAME: u32 = 3_668;
+
+// the display DMA takes a cycle for each of the 8 bytes on 128 scan lines,
+// and the interrupt routine that starts it also counts the timers down
+const DISPLAY_CYCLES: u32 = 1_024;
+const INTERRUPT_CYCLES: u32 = 46;
+
+// what's left for the interpreter
+const BUDGET: u32 = MACHINE_CYCLES_PER_FRAME - DISPLAY_CYCLES - INTERRUPT_CYCLES;
+
+// fetching and decoding an instruction, before it's run
+const FETCH_CYCLES: u32 = 40;
+
+// the extra cycles taken by a skip
+const SKIP_CYCLES: u32 = 4;
+
+/// How long instructions take.
+#[derive(ValueEnum, Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) enum Timing {
+    /// The same for every instruction
+    #[default]
+    Fixed,
+    /// Machine cycles as on the COSMAC VIP (chip8 only)
+    Vip,
+}
+
+/// The machine cycles that `step` took on the VIP, leaving out any wait for
+/// the display.
+fn vip_cycles(step: &Step) -> u32 {
+    let Step {
+        opcode,
+        before,
+        after,
+        ..
+    } = step;
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = u32::from(opcode & 0x000F);
+    let skipped = if after.pc == before.pc.wrapping_add(4) {
+        SKIP_CYCLES
+    } else {
+        0
+    };
+
+    let cycles = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => 24 + 3_078,
+            0x00EE => 10,
+            // a machine code routine, which can't be timed
+            _ => 0,
+        },
+        0x1000 | 0xA000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => 10 + skipped,
+        0x5000 | 0x9000 | 0xE000 => 14 + skipped,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 if n == 0 => 12,
+        0x8000 => 44,
+        // two more to cross into another page
+        0xB000 if (opcode & 0x0F00) != (after.pc & 0xFF00) => 24,
+        0xB000 => 22,
+        0xC000 => 36,
+        0xD000 => {
+            // each row is shifted into place one bit at a time, and rows past
+            // the bottom of the display aren't drawn
+            let shift = u32::from(before.registers[usize::from(x)] % 8);
+            let rows = n.min(32 - u32::from(before.registers[usize::from(y)] % 32));
+            26 + rows * (34 + 4 * shift)
+        }
+        _ => match opcode & 0x00FF {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 19,
+            0x1E | 0x29 => 16,
+            // each decimal digit is counted out by repeated subtraction
+            0x33 => {
+                let value = before.registers[usize::from(x)];
+                80 + 16 * u32::from(value / 100 + value / 10 % 10 + value % 10)
+            }
+            0x55 | 0x65 => 14 + 14 * (u32::from(x) + 1),
+            _ => 0,
+        },
+    };
+
+    FETCH_CYCLES + cycles
+}
+
+/// Decides when each frame's instructions are done.
+#[derive(Debug, Clone)]
+pub(super) struct Clock {
+    timing: Timing,
+    instructions: u8,
+    spent: u32,
+    owed: u32,
+}
+
+impl Clock {
+    pub(super) fn new(timing: Timing) -> Self {
+        Self {
+            timing,
+            instructions: 0,
+            spent: 0,
+            owed: 0,
+        }
+    }
+
+    /// True if `tick` needs to be shown each instruction.
+    pub(super) fn needs_steps(&self) -> bool {
+        self.timing == Timing::Vip
+    }
+
+    /// Starts a frame with what's owed from earlier ones, up to a whole frame,
+    /// leaving any more for the frames after.
+    pub(super) fn start_frame(&mut self) {
+        self.instructions = 0;
+        self.spent = self.owed.min(BUDGET);
+        self.owed -= self.spent;
+    }
+
+    /// True once the frame has no time left for another instruction.
+    pub(super) fn frame_over(&self) -> bool {
+        match self.timing {
+            Timing::Fixed => self.instructions >= CYCLES_PER_FRAME,
+            Timing::Vip => self.spent >= BUDGET,
+        }
+    }
+
+    /// Charges for an instruction, which left the machine `waiting_for_vblank`
+    /// or not. Anything spent beyond the end of a frame is taken from the next.
+    pub(super) fn tick(&mut self, step: Option<&Step>, waiting_for_vblank: bool) {
+        self.instructions = self.instructions.saturating_add(1);
+
+        let Some(step) = step.filter(|_| self.timing == Timing::Vip) else {
+            return;
+        };
+
+        if waiting_for_vblank && step.opcode & 0xF000 == 0xD000 {
+            // a draw waiting for the display interrupt is only done after it,
+            // so its time is taken from the next frame
+            self.owed += vip_cycles(step);
+            return;
+        }
+
+        self.spent += vip_cycles(step);
+        if self.spent > BUDGET {
+            self.owed += self.spent - BUDGET;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vip_cycles;
+    use super::Clock;
+    use super::Timing;
+    use super::BUDGET;
+    use crate::chip8::State;
+    use crate::emulator::Step;
+
+    fn step(opcode: u16, registers: [u8; 16], pc_after: u16) -> Step {
+        let before = State {
+            registers,
+            i: 0,
+            pc: 0x200,
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+        Step {
+            cycle: 0,
+            frame: 0,
+            opcode,
+            before,
+            after: State {
+                pc: pc_after,
+                ..before
+            },
+        }
+    }
+
+    #[test]
+    fn test_vip_cycles() {
+        let mut registers = [0; 16];
+        registers[1] = 3;
+        registers[2] = 31;
+        registers[3] = 255;
+
+        assert_eq!(vip_cycles(&step(0x6001, registers, 0x202)), 46);
+        assert_eq!(vip_cycles(&step(0x3100, registers, 0x202)), 50);
+        assert_eq!(vip_cycles(&step(0x3103, registers, 0x204)), 54);
+        // shifted 3 bits, and only one row fits above the bottom edge
+        assert_eq!(vip_cycles(&step(0xD125, registers, 0x202)), 40 + 26 + 46);
+        assert_eq!(
+            vip_cycles(&step(0xF333, registers, 0x202)),
+            40 + 80 + 16 * 12
+        );
+    }
+
+    #[test]
+    fn test_vip_frames() {
+        // when
+        let mut clock = Clock::new(Timing::Vip);
+        let add = step(0x7001, [0; 16], 0x202);
+        let draw = step(0xD001, [0; 16], 0x202);
+
+        // then
+        let mut counts = Vec::new();
+        for frame in 0..3 {
+            clock.start_frame();
+            let mut count = 0;
+            while !clock.frame_over() {
+                let drawing = frame == 1 && count == 2;
+                clock.tick(Some(if drawing { &draw } else { &add }), drawing);
+                count += 1;
+            }
+            counts.push(count);
+        }
+
+        // verify
+        // 50 cycles each, with the overrun owed to the next frame, and the
+        // draw's 100 owed to the one after
+        assert_eq!(BUDGET.div_ceil(50), 52);
+        assert_eq!(counts, vec![52, 53, 50]);
+    }
+
+    #[test]
+    fn test_vip_frames_pay_off_long_instructions() {
+        // when
+        let mut clock = Clock::new(Timing::Vip);
+        let add = step(0x7001, [0; 16], 0x202);
+        let clear = step(0x00E0, [0; 16], 0x202);
+        let draw = step(0xD00F, [0; 16], 0x202);
+
+        // then
+        let mut counts = Vec::new();
+        for frame in 0..4 {
+            clock.start_frame();
+            let mut count = 0;
+            while !clock.frame_over() {
+                if frame == 0 && count == 51 {
+                    clock.tick(Some(&clear), false);
+                } else if frame == 3 && count == 0 {
+                    // a draw that doesn't wait for the display is paid for now
+                    clock.tick(Some(&draw), false);
+                } else {
+                    clock.tick(Some(&add), false);
+                }
+                count += 1;
+            }
+            counts.push(count);
+        }
+
+        // verify
+        // the clear overruns by more than a whole frame, so the next has no
+        // time at all and the one after starts with the rest
+        assert_eq!(counts, vec![52, 0, 43, 41]);
+    }
+
+    #[test]
+    fn test_fixed_frames() {
+        // when
+        let mut clock = Clock::new(Timing::Fixed);
+
+        // then
+        clock.start_frame();
+        let mut count = 0;
+        while !clock.frame_over() {
+            clock.tick(None, false);
+            count += 1;
+        }
+
+        // verify
+        assert_eq!(count, 20);
+    }
+}
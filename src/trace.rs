@@ -0,0 +1,392 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::chip8;
+use crate::disassembler;
+use crate::emulator::Observer;
+use crate::emulator::Step;
+use crate::headless;
+use crate::Action;
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+
+// the byte-sized registers that are reported when they change: V0-VF, then
+// the delay timer, sound timer and stack pointer
+const REGISTER_NAMES: [&str; 19] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "DT", "ST", "SP",
+];
+
+/// Parses an inclusive range of hex addresses, e.g. `200-2FF`.
+pub(crate) fn parse_pc_range(value: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| anyhow!("invalid range '{value}', expected START-END"))?;
+
+    Ok(headless::parse_address(start)?..=headless::parse_address(end)?)
+}
+
+/// Parses an inclusive range of frames, e.g. `60-120`.
+pub(crate) fn parse_frame_range(value: &str) -> Result<RangeInclusive<u32>> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| anyhow!("invalid range '{value}', expected START-END"))?;
+
+    Ok(start
+        .parse()
+        .with_context(|| format!("invalid frame in '{value}'"))?
+        ..=end
+            .parse()
+            .with_context(|| format!("invalid frame in '{value}'"))?)
+}
+
+/// Parses an opcode class, the first hex digit of the opcode (e.g. `D` for
+/// the sprite drawing instructions).
+pub(crate) fn parse_class(value: &str) -> Result<u8> {
+    u8::from_str_radix(value, 16)
+        .ok()
+        .filter(|c| *c <= 0xF)
+        .ok_or_else(|| anyhow!("invalid opcode class '{value}', expected a hex digit"))
+}
+
+/// Which instructions are written to the trace. An instruction is traced if it
+/// passes every filter given.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Filter {
+    pub(crate) pc: Option<RangeInclusive<u16>>,
+    pub(crate) classes: Vec<u8>,
+    pub(crate) frames: Option<RangeInclusive<u32>>,
+}
+
+impl Filter {
+    fn matches(&self, frame: u32, pc: u16, opcode: u16) -> bool {
+        let class = u8::try_from(opcode >> 12).unwrap_or_default();
+
+        self.pc.as_ref().is_none_or(|r| r.contains(&pc))
+            && (self.classes.is_empty() || self.classes.contains(&class))
+            && self.frames.as_ref().is_none_or(|r| r.contains(&frame))
+    }
+}
+
+/// Where to write a trace, and what to include.
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    pub(crate) path: PathBuf,
+    pub(crate) filter: Filter,
+}
+
+/// One executed instruction, and the registers it changed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Record {
+    cycle: u64,
+    frame: u32,
+    pc: u16,
+    opcode: u16,
+    i: u16,
+    // (index into REGISTER_NAMES, new value)
+    changes: Vec<(usize, u8)>,
+}
+
+impl Record {
+    fn new(
+        cycle: u64,
+        frame: u32,
+        opcode: u16,
+        before: &chip8::State,
+        after: &chip8::State,
+    ) -> Self {
+        let changes = Self::registers(before)
+            .into_iter()
+            .zip(Self::registers(after))
+            .enumerate()
+            .filter(|(_, (b, a))| b != a)
+            .map(|(index, (_, a))| (index, a))
+            .collect();
+
+        Self {
+            cycle,
+            frame,
+            pc: before.pc,
+            opcode,
+            i: after.i,
+            changes,
+        }
+    }
+
+    fn registers(state: &chip8::State) -> [u8; 19] {
+        let mut registers = [0u8; 19];
+        registers[..16].copy_from_slice(&state.registers);
+        registers[16] = state.delay_timer;
+        registers[17] = state.sound_timer;
+        registers[18] = state.sp;
+        registers
+    }
+
+    fn to_text(&self) -> String {
+        let mut line = format!(
+            "{:>10} {:>6} {:04X} {:04X}  {:<20} I={:04X}",
+            self.cycle,
+            self.frame,
+            self.pc,
+            self.opcode,
+            disassembler::disassemble(self.opcode),
+            self.i
+        );
+        for (index, value) in &self.changes {
+            let _ = write!(line, " {}={value:02X}", REGISTER_NAMES[*index]);
+        }
+        line
+    }
+
+    /// Records are little-endian: cycle (8 bytes), frame (4), PC (2),
+    /// opcode (2), I (2), a bitmask of the changed registers (4) and then
+    /// their new values, one byte each in register order.
+    fn write_binary(&self, out: &mut impl Write) -> Result<()> {
+        let mask = self
+            .changes
+            .iter()
+            .fold(0u32, |mask, (index, _)| mask | (1 << index));
+
+        out.write_all(&self.cycle.to_le_bytes())?;
+        out.write_all(&self.frame.to_le_bytes())?;
+        out.write_all(&self.pc.to_le_bytes())?;
+        out.write_all(&self.opcode.to_le_bytes())?;
+        out.write_all(&self.i.to_le_bytes())?;
+        out.write_all(&mask.to_le_bytes())?;
+        for (_, value) in &self.changes {
+            out.write_all(&[*value])?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next record, or `None` at the end of the file.
+    fn read_binary(input: &mut impl Read) -> Result<Option<Self>> {
+        let mut fixed = [0u8; 22];
+        match input.read_exact(&mut fixed) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let (cycle, rest) = fixed.split_at(8);
+        let (frame, rest) = rest.split_at(4);
+        let (pc, rest) = rest.split_at(2);
+        let (opcode, rest) = rest.split_at(2);
+        let (i, mask) = rest.split_at(2);
+        let mask = u32::from_le_bytes(mask.try_into()?);
+
+        let mut changes = Vec::new();
+        for index in (0..REGISTER_NAMES.len()).filter(|i| mask & (1 << i) != 0) {
+            let mut value = [0u8; 1];
+            input
+                .read_exact(&mut value)
+                .context("Truncated trace record")?;
+            changes.push((index, value[0]));
+        }
+
+        Ok(Some(Self {
+            cycle: u64::from_le_bytes(cycle.try_into()?),
+            frame: u32::from_le_bytes(frame.try_into()?),
+            pc: u16::from_le_bytes(pc.try_into()?),
+            opcode: u16::from_le_bytes(opcode.try_into()?),
+            i: u16::from_le_bytes(i.try_into()?),
+            changes,
+        }))
+    }
+}
+
+/// Writes one line per executed instruction, as text or, if the filename ends
+/// in `.bin`, in a compact binary format read back by `chipper trace-dump`.
+#[must_use]
+pub(crate) struct Tracer {
+    out: BufWriter<File>,
+    binary: bool,
+    filter: Filter,
+}
+
+impl Tracer {
+    pub(crate) fn new(options: &Options) -> Result<Self> {
+        let path = &options.path;
+        let file = File::create(path)
+            .with_context(|| format!("Unable to create trace {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        let binary = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("bin"));
+
+        if binary {
+            out.write_all(MAGIC)?;
+            out.write_all(&[VERSION])?;
+        } else {
+            writeln!(out, "{}", Self::header())?;
+        }
+
+        Ok(Self {
+            out,
+            binary,
+            filter: options.filter.clone(),
+        })
+    }
+
+    fn header() -> String {
+        format!(
+            "# {:>8} {:>6} {:4} {:4}  {:<20} {:6} changes",
+            "cycle", "frame", "pc", "op", "instruction", "i"
+        )
+    }
+}
+
+impl Observer for Tracer {
+    fn instruction(&mut self, step: &Step) -> Result<Option<Action>> {
+        if !self.filter.matches(step.frame, step.before.pc, step.opcode) {
+            return Ok(None);
+        }
+
+        let record = Record::new(
+            step.cycle,
+            step.frame,
+            step.opcode,
+            &step.before,
+            &step.after,
+        );
+
+        if self.binary {
+            record.write_binary(&mut self.out)?;
+        } else {
+            writeln!(self.out, "{}", record.to_text())?;
+        }
+
+        Ok(None)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush().map_err(Into::into)
+    }
+}
+
+/// Prints a binary trace file as text, in the same format as a text trace.
+pub(crate) fn dump(path: &Path) -> Result<()> {
+    let file =
+        File::open(path).with_context(|| format!("Unable to open trace {}", path.display()))?;
+    let mut input = BufReader::new(file);
+
+    let mut header = [0u8; 5];
+    input
+        .read_exact(&mut header)
+        .context("Not a binary trace file")?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(anyhow!("{} is not a binary trace file", path.display()));
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    writeln!(out, "{}", Tracer::header())?;
+
+    while let Some(record) = Record::read_binary(&mut input)? {
+        if writeln!(out, "{}", record.to_text()).is_err() {
+            // e.g. piped into head
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use super::parse_class;
+    use super::parse_frame_range;
+    use super::parse_pc_range;
+    use super::Filter;
+    use super::Record;
+    use crate::chip8;
+
+    fn state(pc: u16) -> chip8::State {
+        chip8::State {
+            registers: [0; 16],
+            i: 0x300,
+            pc,
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_ranges() -> Result<(), Error> {
+        assert_eq!(parse_pc_range("200-2ff")?, 0x200..=0x2FF);
+        assert_eq!(parse_frame_range("60-120")?, 60..=120);
+        assert!(parse_pc_range("200").is_err());
+        assert_eq!(parse_class("d")?, 0xD);
+        assert!(parse_class("10").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter {
+            pc: Some(0x200..=0x20F),
+            classes: vec![0x8, 0xD],
+            frames: None,
+        };
+
+        assert!(filter.matches(0, 0x204, 0x8AB5));
+        assert!(!filter.matches(0, 0x210, 0x8AB5));
+        assert!(!filter.matches(0, 0x204, 0x6A02));
+        assert!(Filter::default().matches(9, 0xFFE, 0x0000));
+    }
+
+    #[test]
+    fn test_record_changes() {
+        // when
+        let before = state(0x204);
+        let mut after = state(0x206);
+        after.registers[0xA] = 0x02;
+        after.sound_timer = 0x10;
+
+        // then
+        let record = Record::new(7, 1, 0x6A02, &before, &after);
+
+        // verify
+        assert_eq!(record.changes, vec![(0xA, 0x02), (17, 0x10)]);
+        assert!(record
+            .to_text()
+            .ends_with("LD VA, 0x02          I=0300 VA=02 ST=10"));
+    }
+
+    #[test]
+    fn test_binary_round_trip() -> Result<(), Error> {
+        // when
+        let mut after = state(0x206);
+        after.registers[0xF] = 1;
+        let record = Record::new(1_234_567, 89, 0x8AB5, &state(0x204), &after);
+
+        // then
+        let mut bytes = Vec::new();
+        record.write_binary(&mut bytes)?;
+        let mut input = bytes.as_slice();
+
+        // verify
+        assert_eq!(bytes.len(), 23);
+        assert_eq!(Record::read_binary(&mut input)?, Some(record));
+        assert_eq!(Record::read_binary(&mut input)?, None);
+        Ok(())
+    }
+}
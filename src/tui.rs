@@ -0,0 +1,274 @@
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+use crossterm::cursor;
+use crossterm::event;
+use crossterm::event::Event;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::event::KeyboardEnhancementFlags;
+use crossterm::queue;
+use crossterm::style;
+use crossterm::terminal;
+
+use crate::frontend;
+use crate::frontend::Input;
+use crate::palette;
+use crate::profile;
+use crate::profile::Profile;
+use crate::screenshot;
+use crate::Target;
+
+// Most terminals only report key presses, so a key is released when it hasn't
+// been seen for a while. The first timeout has to outlast the delay before the
+// terminal starts auto-repeating a held key; after that, repeats arrive quickly.
+const FIRST_HOLD: Duration = Duration::from_millis(500);
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+// the same layout as the SDL frontend's keypad
+const KEYPAD: [(char, u8); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xc),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xd),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xe),
+    ('z', 0xa),
+    ('x', 0x0),
+    ('c', 0xb),
+    ('v', 0xf),
+];
+
+/// A key held down, and when it will be released if the terminal doesn't
+/// repeat it.
+#[derive(Debug, Copy, Clone)]
+struct Held {
+    release_at: Instant,
+}
+
+/// Puts the terminal into raw mode on the alternate screen, and restores it
+/// when dropped.
+struct RawTerminal {
+    key_release_events: bool,
+    // the whole display needs redrawing, e.g. after a resize
+    resized: bool,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode().context("Unable to put the terminal into raw mode")?;
+
+        // terminals implementing the kitty keyboard protocol report key-ups
+        let key_release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+
+        let mut stdout = io::stdout();
+        queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        if key_release_events {
+            queue!(
+                stdout,
+                event::PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        stdout.flush()?;
+
+        Ok(Self {
+            key_release_events,
+            resized: true,
+        })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.key_release_events {
+            let _ = queue!(stdout, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(
+            stdout,
+            style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = stdout.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// A frontend in the terminal, drawing two display rows per text row with
+/// Unicode upper half blocks, and sounding the terminal bell for the buzzer.
+#[must_use]
+pub(super) struct Tui {
+    profile: Profile,
+    palette: palette::Palette,
+    terminal: RawTerminal,
+    held: [Option<Held>; 16],
+    sounding: bool,
+}
+
+impl Tui {
+    pub(super) fn new(target: Target, palette: palette::Palette) -> Result<Self> {
+        Ok(Self {
+            profile: profile::for_target(target)?,
+            palette,
+            terminal: RawTerminal::enter()?,
+            held: [None; 16],
+            sounding: false,
+        })
+    }
+
+    fn is_quit(key: KeyEvent) -> bool {
+        // raw mode stops Ctrl+C from raising SIGINT
+        key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, now: Instant, inputs: &mut Vec<Input>) {
+        let KeyCode::Char(c) = key.code else {
+            return;
+        };
+        let Some(num) = KEYPAD
+            .iter()
+            .find_map(|(k, num)| (*k == c.to_ascii_lowercase()).then_some(*num))
+        else {
+            return;
+        };
+        let slot = &mut self.held[usize::from(num)];
+
+        match key.kind {
+            KeyEventKind::Release => {
+                *slot = None;
+                inputs.push(Input::Key {
+                    key: num,
+                    pressed: false,
+                });
+            }
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                let hold = if slot.is_some() {
+                    REPEAT_HOLD
+                } else {
+                    inputs.push(Input::Key {
+                        key: num,
+                        pressed: true,
+                    });
+                    FIRST_HOLD
+                };
+                *slot = Some(Held {
+                    release_at: now + hold,
+                });
+            }
+        }
+    }
+
+    fn draw(&self, gfx: &BitVec, res_scale: u8) -> Result<()> {
+        let width = usize::from(self.profile.screen_width());
+        let pixels = screenshot::indexed(gfx, self.profile, res_scale, 1);
+
+        let mut stdout = io::stdout().lock();
+
+        for (row, pair) in (0u16..).zip(pixels.chunks(width * 2)) {
+            let (top, bottom) = pair.split_at(width.min(pair.len()));
+            let mut colours = None;
+
+            queue!(stdout, cursor::MoveTo(0, row))?;
+            for (x, upper) in top.iter().enumerate() {
+                let lower = bottom.get(x).copied().unwrap_or(0);
+                let cell = (*upper, lower);
+
+                if colours != Some(cell) {
+                    queue!(
+                        stdout,
+                        style::SetForegroundColor(self.colour(*upper)),
+                        style::SetBackgroundColor(self.colour(lower))
+                    )?;
+                    colours = Some(cell);
+                }
+                queue!(stdout, style::Print('▀'))?;
+            }
+            queue!(stdout, style::ResetColor)?;
+        }
+
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn colour(&self, pixel: u8) -> style::Color {
+        let c = self.palette.colour(usize::from(pixel));
+        style::Color::Rgb {
+            r: c.r,
+            g: c.g,
+            b: c.b,
+        }
+    }
+}
+
+impl frontend::Display for Tui {
+    fn present(&mut self, gfx: &BitVec, res_scale: u8, changed: bool) -> Result<()> {
+        if changed || std::mem::take(&mut self.terminal.resized) {
+            self.draw(gfx, res_scale)?;
+        }
+
+        Ok(())
+    }
+
+    fn palette(&self) -> palette::Palette {
+        self.palette
+    }
+}
+
+impl frontend::Sound for Tui {
+    /// Rings the terminal bell when the buzzer starts.
+    fn set_sounding(&mut self, sounding: bool) {
+        if sounding && !self.sounding {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|()| stdout.flush());
+        }
+        self.sounding = sounding;
+    }
+}
+
+impl frontend::InputSource for Tui {
+    fn poll(&mut self) -> Result<Vec<Input>> {
+        let now = Instant::now();
+        let mut inputs = Vec::new();
+
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) if Self::is_quit(key) => inputs.push(Input::Quit),
+                Event::Key(key) => self.handle_key(key, now, &mut inputs),
+                Event::Resize(..) => {
+                    queue!(io::stdout(), terminal::Clear(terminal::ClearType::All))?;
+                    self.terminal.resized = true;
+                }
+                _ => {}
+            }
+        }
+
+        if !self.terminal.key_release_events {
+            for (key, held) in (0u8..).zip(self.held.iter_mut()) {
+                if held.is_some_and(|h| h.release_at <= now) {
+                    *held = None;
+                    inputs.push(Input::Key {
+                        key,
+                        pressed: false,
+                    });
+                }
+            }
+        }
+
+        Ok(inputs)
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::audio;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// Captures the emulator's audio output to a WAV file.
+///
+/// Samples are generated from the emulated sound state once per 60 Hz frame,
+/// rather than tapped from the SDL callback, so the capture runs at exactly
+/// the emulated rate and lines up with frame recordings made alongside it.
+#[must_use]
+pub(crate) struct WavCapture {
+    writer: hound::WavWriter<BufWriter<File>>,
+    wave: audio::SquareWave,
+    buffer: Vec<f32>,
+    frame: u64,
+}
+
+impl WavCapture {
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: audio::SAMPLE_RATE.unsigned_abs(),
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+
+        Ok(Self {
+            writer: hound::WavWriter::create(path, spec)
+                .with_context(|| format!("Unable to create audio capture {}", path.display()))?,
+            wave: audio::SquareWave::new(audio::SAMPLE_RATE),
+            buffer: Vec::new(),
+            frame: 0,
+        })
+    }
+
+    /// Adds one 60 Hz frame of audio, with the buzzer sounding if `sound` is set.
+    pub(crate) fn add_frame(&mut self, sound: bool) -> Result<()> {
+        self.buffer.clear();
+        self.buffer.resize(Self::samples_in_frame(self.frame), 0.0);
+
+        if sound {
+            self.wave.fill(&mut self.buffer);
+        }
+
+        for sample in &self.buffer {
+            self.writer.write_sample(*sample)?;
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+
+    fn samples_in_frame(frame: u64) -> usize {
+        // frame boundaries are rounded down to whole samples, so rates that
+        // aren't a multiple of 60 Hz don't drift
+        let rate = u64::from(audio::SAMPLE_RATE.unsigned_abs());
+        let samples = ((frame + 1) * rate) / FRAMES_PER_SECOND - (frame * rate) / FRAMES_PER_SECOND;
+
+        usize::try_from(samples).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WavCapture;
+
+    #[test]
+    fn test_samples_in_frame_total_one_second() {
+        let total: usize = (0..60).map(WavCapture::samples_in_frame).sum();
+
+        assert_eq!(total, 44_100);
+        assert_eq!(WavCapture::samples_in_frame(0), 735);
+    }
+}
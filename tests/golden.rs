@@ -0,0 +1,213 @@
+//! Runs the bundled test suite ROMs headlessly and compares the final display
+//! against the golden images in `tests/golden/`.
+//!
+//! Set `CHIPPER_BLESS=1` to (re)write the golden images from the current build
+//! instead of comparing against them.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+
+const SUITE: &str = "programs/chip8-test-suite";
+const GOLDEN: &str = "tests/golden";
+
+struct Case {
+    rom: &'static str,
+    target: &'static str,
+    frames: u32,
+    // menu choices, as --press FRAME:KEY arguments
+    presses: &'static [&'static str],
+}
+
+impl Case {
+    fn golden_path(&self) -> PathBuf {
+        let stem = self.rom.trim_end_matches(".ch8");
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join(GOLDEN)
+            .join(format!("{stem}-{}.pbm", self.target))
+    }
+
+    fn run(&self, dump: &Path) -> Result<(), Error> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_chipper"));
+        command
+            .arg("--headless")
+            .args(["--target", self.target])
+            .args(["--frames", &self.frames.to_string()])
+            .arg("--dump")
+            .arg(dump)
+            .arg("--file")
+            .arg(root.join(SUITE).join(self.rom));
+        for press in self.presses {
+            command.args(["--press", press]);
+        }
+
+        let output = command.output().context("Unable to run chipper")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} ({}) failed: {}",
+                self.rom,
+                self.target,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let dump = env::temp_dir().join(format!(
+            "chipper-{}-{}-{}.pbm",
+            std::process::id(),
+            self.rom.trim_end_matches(".ch8"),
+            self.target
+        ));
+        self.run(&dump)?;
+        let actual = fs::read_to_string(&dump)?;
+        fs::remove_file(&dump)?;
+
+        let golden = self.golden_path();
+        if env::var_os("CHIPPER_BLESS").is_some() {
+            fs::write(&golden, actual)?;
+            return Ok(());
+        }
+
+        let expected = fs::read_to_string(&golden)
+            .with_context(|| format!("Missing golden image {}", golden.display()))?;
+        assert!(
+            actual == expected,
+            "{} ({}) doesn't match {}",
+            self.rom,
+            self.target,
+            golden.display()
+        );
+
+        Ok(())
+    }
+}
+
+fn check_all(cases: &[Case]) -> Result<(), Error> {
+    for case in cases {
+        case.check()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_chip8_logo() -> Result<(), Error> {
+    check_all(&[Case {
+        rom: "1-chip8-logo.ch8",
+        target: "chip8",
+        frames: 60,
+        presses: &[],
+    }])
+}
+
+#[test]
+fn test_ibm_logo() -> Result<(), Error> {
+    check_all(&[Case {
+        rom: "2-ibm-logo.ch8",
+        target: "chip8",
+        frames: 60,
+        presses: &[],
+    }])
+}
+
+#[test]
+fn test_corax_plus() -> Result<(), Error> {
+    check_all(&[Case {
+        rom: "3-corax+.ch8",
+        target: "chip8",
+        frames: 120,
+        presses: &[],
+    }])
+}
+
+#[test]
+fn test_flags() -> Result<(), Error> {
+    check_all(&[
+        Case {
+            rom: "4-flags.ch8",
+            target: "chip8",
+            frames: 120,
+            presses: &[],
+        },
+        Case {
+            rom: "4-flags.ch8",
+            target: "xo-chip",
+            frames: 120,
+            presses: &[],
+        },
+    ])
+}
+
+#[test]
+fn test_quirks() -> Result<(), Error> {
+    // the first menu picks the platform, and SUPER-CHIP then asks for
+    // modern (1) or legacy (2) behaviour
+    check_all(&[
+        Case {
+            rom: "5-quirks.ch8",
+            target: "chip8",
+            frames: 900,
+            presses: &["150:1"],
+        },
+        Case {
+            rom: "5-quirks.ch8",
+            target: "super-chip",
+            frames: 900,
+            presses: &["150:2", "300:1"],
+        },
+        Case {
+            rom: "5-quirks.ch8",
+            target: "super-chip-legacy",
+            frames: 900,
+            presses: &["150:2", "300:2"],
+        },
+        Case {
+            rom: "5-quirks.ch8",
+            target: "xo-chip",
+            frames: 900,
+            presses: &["150:3"],
+        },
+    ])
+}
+
+#[test]
+fn test_keypad() -> Result<(), Error> {
+    // FX0A test, answered with key A
+    check_all(&[Case {
+        rom: "6-keypad.ch8",
+        target: "chip8",
+        frames: 600,
+        presses: &["150:3", "300:A"],
+    }])
+}
+
+#[test]
+fn test_beep() -> Result<(), Error> {
+    check_all(&[Case {
+        rom: "7-beep.ch8",
+        target: "chip8",
+        frames: 60,
+        presses: &[],
+    }])
+}
+
+#[test]
+fn test_scrolling() -> Result<(), Error> {
+    // SUPER-CHIP, then the hires test
+    check_all(&[Case {
+        rom: "8-scrolling.ch8",
+        target: "super-chip",
+        frames: 900,
+        presses: &["150:1", "300:2"],
+    }])
+}